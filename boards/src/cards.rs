@@ -46,6 +46,10 @@ impl<T> Deck<T> {
         self.cards.iter().take(count)
     }
 
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.cards.iter()
+    }
+
     pub fn put_top(&mut self, card: T) {
         self.cards.push_front(card)
     }
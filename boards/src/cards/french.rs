@@ -1,5 +1,6 @@
 use crate::cards;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Hash, Eq)]
 pub enum Suite {
@@ -71,6 +72,43 @@ impl fmt::Display for Card {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub struct ParseCardError(String);
+
+impl fmt::Display for ParseCardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid card {}", self.0)
+    }
+}
+
+impl FromStr for Card {
+    type Err = ParseCardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Suite::*;
+        let err = || ParseCardError(s.to_owned());
+        let mut chars = s.chars();
+        let suite = match chars.next_back().ok_or_else(err)? {
+            '♥' => Hearts,
+            '♦' => Diamonds,
+            '♣' => Clubs,
+            '♠' => Spades,
+            _ => return Err(err()),
+        };
+        let rank = match chars.as_str() {
+            "A" => ACE,
+            "J" => JACK,
+            "Q" => QUEEN,
+            "K" => KING,
+            r => match r.parse() {
+                Ok(r) if (2..=10).contains(&r) => r,
+                _ => return Err(err()),
+            },
+        };
+        Ok(Card::new(rank, suite))
+    }
+}
+
 pub const ACE: u8 = 1;
 pub const JACK: u8 = 11;
 pub const QUEEN: u8 = 12;
@@ -154,6 +192,17 @@ mod tests {
         assert_eq!(Card::new_unchecked(KING, Suite::Diamonds).to_string(), "K♦");
     }
 
+    #[test]
+    pub fn parse() {
+        for card in standard_52_deck().iter() {
+            assert_eq!(card.to_string().parse(), Ok(*card));
+        }
+        assert!("1♠".parse::<Card>().is_err());
+        assert!("B♠".parse::<Card>().is_err());
+        assert!("10".parse::<Card>().is_err());
+        assert!("".parse::<Card>().is_err());
+    }
+
     #[test]
     pub fn std() {
         assert_eq!(standard_52_deck().len(), 52);
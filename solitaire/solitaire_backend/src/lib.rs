@@ -79,6 +79,7 @@ impl MemoryTableau {
     }
 }
 
+#[derive(Clone)]
 pub struct Tableau {
     pub downfaced_len: usize,
     pub upturned: Vec<Card>,
@@ -140,6 +141,118 @@ impl IndexMut<Suite> for Foundations {
 }
 
 pub const TABLEAUS_COUNT: usize = 7;
+/// Cards in a game, no pile holds more.
+pub const DECK_SIZE: usize = 52;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Variant {
+    /// Only the bottom card of each tableau is dealt face up.
+    #[default]
+    Klondike,
+    /// Every tableau card is dealt face up.
+    Thoughtful,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScoringMode {
+    #[default]
+    Unscored,
+    /// Windows-style scoring: points for building and revealing, penalty for redealing.
+    Standard,
+    /// Starts at -52 and earns 5 per card sent to a foundation.
+    Vegas,
+}
+
+pub enum ParseOptionError {
+    Invalid(String),
+}
+
+impl FromStr for Variant {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "klondike" => Ok(Variant::Klondike),
+            "thoughtful" => Ok(Variant::Thoughtful),
+            _ => Err(ParseOptionError::Invalid(format!("Unknown variant {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Variant::Klondike => write!(f, "klondike"),
+            Variant::Thoughtful => write!(f, "thoughtful"),
+        }
+    }
+}
+
+impl FromStr for ScoringMode {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unscored" => Ok(ScoringMode::Unscored),
+            "standard" => Ok(ScoringMode::Standard),
+            "vegas" => Ok(ScoringMode::Vegas),
+            _ => Err(ParseOptionError::Invalid(format!(
+                "Unknown scoring mode {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ScoringMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScoringMode::Unscored => write!(f, "unscored"),
+            ScoringMode::Standard => write!(f, "standard"),
+            ScoringMode::Vegas => write!(f, "vegas"),
+        }
+    }
+}
+
+/// Rules a game is played with. They are fixed when the game is dealt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameOptions {
+    pub variant: Variant,
+    /// Cards turned from the draw pile on each draw, 1 or 3.
+    pub draw_count: usize,
+    /// How many times the waste may be turned back into the draw pile, unlimited when `None`.
+    pub redeal_limit: Option<u32>,
+    pub scoring: ScoringMode,
+    /// Seed the deck was shuffled with, kept so the deal can be described and replayed.
+    pub seed: Option<u64>,
+    /// Automatically send cards to the foundations once nothing can be built on them.
+    pub auto_foundation: bool,
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        Self {
+            variant: Variant::default(),
+            draw_count: 1,
+            redeal_limit: None,
+            scoring: ScoringMode::default(),
+            seed: None,
+            auto_foundation: false,
+        }
+    }
+}
+
+impl GameOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.draw_count != 1 && self.draw_count != 3 {
+            return Err(format!(
+                "Draw count must be 1 or 3, got {}",
+                self.draw_count
+            ));
+        }
+        Ok(())
+    }
+}
 
 #[async_trait]
 pub trait Game {
@@ -147,16 +260,33 @@ pub trait Game {
     fn upturned(&self) -> Option<Card>;
     fn foundations(&self) -> Foundations;
     fn tableaus<'a>(&'a self) -> Vec<Tableau>;
+    /// Current score, `None` when the game is unscored.
+    fn score(&self) -> Option<i32>;
 
     async fn act(&mut self, action: Action) -> ActionResult;
 }
 
+/// What a player may see of a game: face-down cards are only counted, and the options leave out
+/// the seed, which gives the whole deal away. Whatever is shown of a game is built from it.
+#[derive(Clone)]
+pub struct PlayerView {
+    pub draw_pile_size: usize,
+    pub upturned: Option<Card>,
+    pub foundations: Foundations,
+    pub tableaus: Vec<Tableau>,
+    pub score: Option<i32>,
+    pub options: GameOptions,
+}
+
 #[derive(Clone)]
 pub struct MemoryGame {
     draw_pile: FrenchDeck,
     waste: FrenchDeck,
     foundations: Foundations,
     tableaus: [MemoryTableau; TABLEAUS_COUNT],
+    options: GameOptions,
+    redeals: u32,
+    score: i32,
 }
 
 #[async_trait]
@@ -183,111 +313,202 @@ impl Game for MemoryGame {
             .collect()
     }
 
+    fn score(&self) -> Option<i32> {
+        match self.options.scoring {
+            ScoringMode::Unscored => None,
+            _ => Some(self.score),
+        }
+    }
+
     async fn act(&mut self, action: Action) -> ActionResult {
-        use Action::*;
+        let downfaced = self.downfaced_count();
+        let result = match action {
+            Action::Draw => self.draw(),
+            Action::BuildFoundation { src } => self.build_foundation(src),
+            Action::BuildTableau { src, dst } => self.build_tableau(src, dst),
+        };
+        let result = match result {
+            ActionResult::OnGoing if self.options.auto_foundation => self.auto_foundation(),
+            result => result,
+        };
+        if self.options.scoring == ScoringMode::Standard {
+            self.add_score(5 * (downfaced - self.downfaced_count()) as i32);
+        }
+        result
+    }
+}
+
+impl MemoryGame {
+    fn downfaced_count(&self) -> usize {
+        self.tableaus.iter().map(|t| t.downfaced_len()).sum()
+    }
+
+    fn add_score(&mut self, points: i32) {
+        self.score += points;
+        if self.options.scoring == ScoringMode::Standard {
+            self.score = self.score.max(0);
+        }
+    }
+
+    fn draw(&mut self) -> ActionResult {
+        if self.draw_pile.is_empty() && !self.waste.is_empty() {
+            if matches!(self.options.redeal_limit, Some(limit) if self.redeals >= limit) {
+                return ActionResult::Failed(String::from("No redeals left"));
+            }
+            self.draw_pile.put_bottom_many(self.waste.draw_all().rev());
+            self.redeals += 1;
+            if self.options.scoring == ScoringMode::Standard {
+                self.add_score(if self.options.draw_count == 1 {
+                    -100
+                } else {
+                    -20
+                });
+            }
+        }
+        let count = self.options.draw_count.min(self.draw_pile.len());
+        for c in self.draw_pile.draw_many(count) {
+            self.waste.put_top(c);
+        }
+        ActionResult::OnGoing
+    }
+
+    fn build_foundation(&mut self, src: FoundationSource) -> ActionResult {
         use ActionResult::*;
-        match action {
-            Draw => {
-                if self.draw_pile.is_empty() {
-                    self.draw_pile.put_bottom_many(self.waste.draw_all().rev());
-                }
-                if let Some(c) = self.draw_pile.draw() {
-                    self.waste.put_top(c);
+        use FoundationSource::*;
+        let maybe_card = match src {
+            Upturned => self.waste.peek(),
+            Tableau(idx) => {
+                if idx >= self.tableaus.len() {
+                    None
+                } else {
+                    self.tableaus[idx].pile.last()
                 }
-                OnGoing
             }
-            BuildFoundation { src } => {
-                use FoundationSource::*;
-                let maybe_card = match src {
-                    Upturned => self.waste.peek(),
-                    Tableau(idx) => {
-                        if idx >= self.tableaus.len() {
-                            None
-                        } else {
-                            self.tableaus[idx].pile.last()
-                        }
+        };
+        if let Some(c) = maybe_card {
+            let dest: &mut u8 = &mut self.foundations[c.suite()];
+            if c.rank() != *dest + 1 {
+                Failed(String::from("Invalid rank"))
+            } else {
+                *dest = c.rank();
+                std::mem::drop(maybe_card);
+                match src {
+                    Tableau(idx) => self.tableaus[idx].remove_bottom(),
+                    Upturned => {
+                        self.waste.draw();
                     }
                 };
-                if let Some(c) = maybe_card {
-                    let dest: &mut u8 = &mut self.foundations[c.suite()];
-                    if c.rank() != *dest + 1 {
-                        Failed(String::from("Invalid rank"))
-                    } else {
-                        *dest = c.rank();
-                        std::mem::drop(maybe_card);
-                        match src {
-                            Tableau(idx) => self.tableaus[idx].remove_bottom(),
-                            Upturned => {
-                                self.waste.draw();
-                            }
-                        };
-                        if self.foundations.iter().all(|f| f.value == KING) {
-                            Victory
-                        } else {
-                            OnGoing
-                        }
-                    }
+                match self.options.scoring {
+                    ScoringMode::Unscored => (),
+                    ScoringMode::Standard => self.add_score(10),
+                    ScoringMode::Vegas => self.add_score(5),
+                }
+                if self.foundations.iter().all(|f| f.value == KING) {
+                    Victory
                 } else {
-                    Failed(String::from("No source card"))
+                    OnGoing
                 }
             }
-            BuildTableau { src, dst } => {
-                use TableauSource::*;
-                let joint = match src {
-                    Upturned => self.waste.peek(),
-                    Tableau { index, size } => {
-                        if index >= self.tableaus.len()
-                            || size > self.tableaus[index].upturned_len()
-                            || index == dst
-                        {
-                            None
-                        } else {
-                            let tableau = &self.tableaus[index];
-                            tableau.upturned(size - tableau.upturned_len())
-                        }
+        } else {
+            Failed(String::from("No source card"))
+        }
+    }
+
+    fn build_tableau(&mut self, src: TableauSource, dst: usize) -> ActionResult {
+        use ActionResult::*;
+        use TableauSource::*;
+        let joint = match src {
+            Upturned => self.waste.peek(),
+            Tableau { index, size } => {
+                if index >= self.tableaus.len()
+                    || size > self.tableaus[index].upturned_len()
+                    || index == dst
+                {
+                    None
+                } else {
+                    let tableau = &self.tableaus[index];
+                    tableau.upturned(tableau.upturned_len() - size)
+                }
+            }
+        };
+        if dst >= self.tableaus.len() {
+            Failed(String::from("Invalid dest"))
+        } else {
+            let dst_tableau = &self.tableaus[dst];
+            if let Some(joint) = joint {
+                let valid = match dst_tableau.bottom() {
+                    None => joint.rank() == KING,
+                    Some(bottom) => {
+                        joint.suite().color() != bottom.suite().color()
+                            && joint.rank() == bottom.rank() - 1
                     }
                 };
-                if dst >= self.tableaus.len() {
-                    Failed(String::from("Invalid dest"))
-                } else {
-                    let dst_tableau = &self.tableaus[dst];
-                    if let Some(joint) = joint {
-                        let valid = match dst_tableau.bottom() {
-                            None => joint.rank() == KING,
-                            Some(bottom) => {
-                                joint.suite().color() != bottom.suite().color()
-                                    && joint.rank() == bottom.rank() - 1
-                            }
-                        };
-                        if valid {
-                            let cards = match src {
-                                Upturned => vec![self.waste.draw().unwrap()],
-                                Tableau { index, size } => self.tableaus[index].take_upturned(size),
-                            };
-                            self.tableaus[dst].add_upturned(cards.into_iter());
-                            OnGoing
-                        } else {
-                            Failed(String::from("Invalid target"))
-                        }
-                    } else {
-                        Failed(String::from("Invalid source"))
+                if valid {
+                    let cards = match src {
+                        Upturned => vec![self.waste.draw().unwrap()],
+                        Tableau { index, size } => self.tableaus[index].take_upturned(size),
+                    };
+                    self.tableaus[dst].add_upturned(cards.into_iter());
+                    if src == Upturned && self.options.scoring == ScoringMode::Standard {
+                        self.add_score(5);
                     }
+                    OnGoing
+                } else {
+                    Failed(String::from("Invalid target"))
                 }
+            } else {
+                Failed(String::from("Invalid source"))
+            }
+        }
+    }
+
+    /// A card is safe to send to its foundation when no card that could still be built on it in
+    /// the tableaus would be left behind, i.e. both foundations of the other colour are close.
+    fn is_safe_for_foundation(&self, card: &Card) -> bool {
+        let color = card.suite().color();
+        card.rank() <= 2
+            || self
+                .foundations
+                .iter()
+                .filter(|f| f.suite.color() != color)
+                .all(|f| f.value + 1 >= card.rank())
+    }
+
+    fn auto_foundation(&mut self) -> ActionResult {
+        loop {
+            let waste = self.waste.peek().map(|c| (FoundationSource::Upturned, *c));
+            let tableaus = self
+                .tableaus
+                .iter()
+                .enumerate()
+                .filter_map(|(i, t)| t.bottom().map(|c| (FoundationSource::Tableau(i), *c)));
+            let src = waste.into_iter().chain(tableaus).find(|(_, c)| {
+                c.rank() == self.foundations[c.suite()] + 1 && self.is_safe_for_foundation(c)
+            });
+            match src {
+                None => return ActionResult::OnGoing,
+                Some((src, _)) => match self.build_foundation(src) {
+                    ActionResult::OnGoing => (),
+                    result => return result,
+                },
             }
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum FoundationSource {
     Upturned,
     Tableau(usize),
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum TableauSource {
     Upturned,
     Tableau { index: usize, size: usize },
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum Action {
     Draw,
     BuildFoundation { src: FoundationSource },
@@ -333,24 +554,77 @@ impl FromStr for Action {
     }
 }
 
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Draw => write!(f, "draw"),
+            Action::BuildFoundation { src } => match src {
+                FoundationSource::Upturned => write!(f, "build u"),
+                FoundationSource::Tableau(index) => write!(f, "build {}", index),
+            },
+            Action::BuildTableau { src, dst } => match src {
+                TableauSource::Upturned => write!(f, "move u {}", dst),
+                TableauSource::Tableau { index, size } => {
+                    write!(f, "move {} {} {}", index, size, dst)
+                }
+            },
+        }
+    }
+}
+
 pub enum ActionResult {
     Victory,
     OnGoing,
     Failed(String),
 }
 
+pub fn shuffled_deck(rand: &mut impl RandomEngine) -> FrenchDeck {
+    let mut deck = standard_52_deck();
+    FrenchDeck::shuffle(&mut deck, rand);
+    deck
+}
+
 impl MemoryGame {
     pub fn new(rand: &mut impl RandomEngine) -> Self {
-        let mut draw_pile = standard_52_deck();
-        FrenchDeck::shuffle(&mut draw_pile, rand);
+        Self::from_deck(shuffled_deck(rand))
+    }
+
+    /// Rules the game is played with, as it was dealt.
+    pub fn options(&self) -> &GameOptions {
+        &self.options
+    }
 
+    pub fn player_view(&self) -> PlayerView {
+        PlayerView {
+            draw_pile_size: self.draw_pile_size(),
+            upturned: self.upturned(),
+            foundations: self.foundations(),
+            tableaus: self.tableaus(),
+            score: self.score(),
+            options: GameOptions {
+                seed: None,
+                ..self.options
+            },
+        }
+    }
+
+    /// Deals a game from `deck` without shuffling it, so a game can be rebuilt from its deck order.
+    pub fn from_deck(draw_pile: FrenchDeck) -> Self {
+        Self::with_options(draw_pile, GameOptions::default())
+    }
+
+    /// Deals a game from `deck` without shuffling it, played with the given `options`.
+    pub fn with_options(mut draw_pile: FrenchDeck, options: GameOptions) -> Self {
         let tableaus = {
             let mut arr: [MaybeUninit<MemoryTableau>; TABLEAUS_COUNT] =
                 unsafe { MaybeUninit::uninit().assume_init() };
             for i in 0..arr.len() {
                 arr[i].write(MemoryTableau {
                     pile: draw_pile.draw_many(i + 1).collect(),
-                    upturned: 1,
+                    upturned: match options.variant {
+                        Variant::Klondike => 1,
+                        Variant::Thoughtful => i + 1,
+                    },
                 });
             }
             unsafe { std::mem::transmute(arr) }
@@ -361,6 +635,12 @@ impl MemoryGame {
             waste: FrenchDeck::new(),
             foundations: Foundations::default(),
             tableaus,
+            options,
+            redeals: 0,
+            score: match options.scoring {
+                ScoringMode::Vegas => -52,
+                _ => 0,
+            },
         }
     }
 }
@@ -383,6 +663,9 @@ where
             r => write!(f, " {: >3}", Card::new_unchecked(r, suite))?,
         }
     }
+    if let Some(score) = game.score() {
+        write!(f, "    Score: {}", score)?;
+    }
     writeln!(f)?;
     writeln!(f)?;
     for line in 0.. {
@@ -425,3 +708,198 @@ impl fmt::Display for MemoryGame {
         display(self, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A deck with the given cards at their positions, the other cards filling the rest from the
+    /// kings down, so they are of no use early in the game.
+    fn deck_with(placed: &[(usize, &str)]) -> FrenchDeck {
+        let placed: Vec<(usize, Card)> = placed
+            .iter()
+            .map(|(i, c)| (*i, c.parse().unwrap()))
+            .collect();
+        let mut rest = standard_52_deck()
+            .iter()
+            .rev()
+            .filter(|c| placed.iter().all(|(_, p)| p != *c))
+            .copied()
+            .collect::<Vec<_>>()
+            .into_iter();
+        (0..52)
+            .map(|i| match placed.iter().find(|(p, _)| *p == i) {
+                Some((_, c)) => *c,
+                None => rest.next().unwrap(),
+            })
+            .collect()
+    }
+
+    fn game_with(placed: &[(usize, &str)], options: GameOptions) -> MemoryGame {
+        MemoryGame::with_options(deck_with(placed), options)
+    }
+
+    fn upturned(game: &MemoryGame, index: usize) -> Vec<String> {
+        game.tableaus()[index]
+            .upturned
+            .iter()
+            .map(|c| c.to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn partial_stacks_are_moved() {
+        let mut game = game_with(
+            &[(0, "8♠"), (3, "A♦"), (4, "7♥"), (5, "6♠")],
+            GameOptions {
+                variant: Variant::Thoughtful,
+                ..GameOptions::default()
+            },
+        );
+        let result = game
+            .act(Action::BuildTableau {
+                src: TableauSource::Tableau { index: 2, size: 2 },
+                dst: 0,
+            })
+            .await;
+        assert!(matches!(result, ActionResult::OnGoing));
+        assert_eq!(upturned(&game, 0), ["8♠", "7♥", "6♠"]);
+        assert_eq!(upturned(&game, 2), ["A♦"]);
+    }
+
+    #[tokio::test]
+    async fn options_change_the_deal_and_draws() {
+        let klondike = MemoryGame::from_deck(standard_52_deck());
+        let downfaced: Vec<_> = klondike
+            .tableaus()
+            .iter()
+            .map(|t| t.downfaced_len)
+            .collect();
+        assert_eq!(downfaced, [0, 1, 2, 3, 4, 5, 6]);
+
+        let mut thoughtful = MemoryGame::with_options(
+            standard_52_deck(),
+            GameOptions {
+                variant: Variant::Thoughtful,
+                draw_count: 3,
+                ..GameOptions::default()
+            },
+        );
+        assert!(thoughtful
+            .tableaus()
+            .iter()
+            .enumerate()
+            .all(|(i, t)| t.downfaced_len == 0 && t.upturned.len() == i + 1));
+        thoughtful.act(Action::Draw).await;
+        assert_eq!(thoughtful.draw_pile_size(), 21);
+        // The last of the three turned cards is the one played
+        assert_eq!(thoughtful.upturned(), "5♣".parse().ok());
+    }
+
+    #[tokio::test]
+    async fn redeals_are_limited() {
+        let mut game = MemoryGame::with_options(
+            standard_52_deck(),
+            GameOptions {
+                draw_count: 3,
+                redeal_limit: Some(1),
+                ..GameOptions::default()
+            },
+        );
+        for _ in 0..8 {
+            game.act(Action::Draw).await;
+        }
+        assert_eq!(game.draw_pile_size(), 0);
+        assert!(matches!(
+            game.act(Action::Draw).await,
+            ActionResult::OnGoing
+        ));
+        assert_eq!(game.draw_pile_size(), 21);
+        for _ in 0..7 {
+            game.act(Action::Draw).await;
+        }
+        assert!(matches!(
+            game.act(Action::Draw).await,
+            ActionResult::Failed(s) if s == "No redeals left"
+        ));
+        assert_eq!(game.draw_pile_size(), 0);
+    }
+
+    #[tokio::test]
+    async fn standard_scoring_follows_each_action() {
+        let mut game = game_with(
+            &[(0, "A♠"), (1, "5♣"), (2, "2♠"), (28, "4♥")],
+            GameOptions {
+                scoring: ScoringMode::Standard,
+                ..GameOptions::default()
+            },
+        );
+        assert_eq!(game.score(), Some(0));
+        let build = |index| Action::BuildFoundation {
+            src: FoundationSource::Tableau(index),
+        };
+        game.act(build(0)).await;
+        assert_eq!(game.score(), Some(10));
+        // Building reveals the 5♣, which is worth 5 more
+        game.act(build(1)).await;
+        assert_eq!(game.score(), Some(25));
+        game.act(Action::Draw).await;
+        assert_eq!(game.score(), Some(25));
+        game.act(Action::BuildTableau {
+            src: TableauSource::Upturned,
+            dst: 1,
+        })
+        .await;
+        assert_eq!(game.score(), Some(30));
+
+        // Redealing costs 100 points, the score never going below zero
+        while game.draw_pile_size() > 0 {
+            game.act(Action::Draw).await;
+        }
+        game.act(Action::Draw).await;
+        assert_eq!(game.score(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn vegas_scoring_pays_for_foundations() {
+        let mut game = game_with(
+            &[(0, "A♠"), (1, "5♣"), (2, "2♠")],
+            GameOptions {
+                scoring: ScoringMode::Vegas,
+                ..GameOptions::default()
+            },
+        );
+        assert_eq!(game.score(), Some(-52));
+        for index in [0, 1] {
+            game.act(Action::BuildFoundation {
+                src: FoundationSource::Tableau(index),
+            })
+            .await;
+        }
+        assert_eq!(game.score(), Some(-42));
+        assert_eq!(MemoryGame::from_deck(standard_52_deck()).score(), None);
+    }
+
+    #[tokio::test]
+    async fn safe_cards_are_sent_to_foundations() {
+        let mut game = game_with(
+            &[(0, "A♠"), (1, "A♥"), (2, "2♠"), (5, "3♠")],
+            GameOptions {
+                auto_foundation: true,
+                ..GameOptions::default()
+            },
+        );
+        assert!(matches!(
+            game.act(Action::Draw).await,
+            ActionResult::OnGoing
+        ));
+        let foundations = game.foundations();
+        assert_eq!(foundations[Suite::Spades], 2);
+        assert_eq!(foundations[Suite::Hearts], 1);
+        // The 3♠ could still hold a red 2, it stays until both red foundations are there
+        assert_eq!(
+            game.tableaus()[2].upturned.last(),
+            "3♠".parse().ok().as_ref()
+        );
+    }
+}
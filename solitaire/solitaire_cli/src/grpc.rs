@@ -1,94 +1,841 @@
+// Calls answer a `tonic::Status` on failure, large as it is, and so do the functions around them
+#![allow(clippy::result_large_err)]
+
 use async_trait::async_trait;
-use solitaire_grpc::{proto::solitaire_client::SolitaireClient, ProtoError};
+use boards::random_engine::{DefaultRandomEngine, RandomEngine};
+use solitaire_grpc::proto::play_response::{self, move_result};
+use solitaire_grpc::proto::{play_request, solitaire_client::SolitaireClient};
+use solitaire_grpc::{OutOfSync, ProtoError, SyncedState};
+use std::collections::VecDeque;
 use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Moves sent ahead of their results, we only ever wait for one.
+const PLAY_CHANNEL_SIZE: usize = 4;
+/// Attempts to reach the server again once it was lost, waiting twice as long after each.
+const RECONNECT_ATTEMPTS: u32 = 8;
+const FIRST_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Times a move is sent, the first one included, when the server is lost while playing it.
+const MOVE_ATTEMPTS: u32 = 3;
 
 pub struct GrpcGame {
     client: SolitaireClient<tonic::transport::Channel>,
     id: String,
-    state: solitaire_grpc::proto::State,
+    /// Sent with every request, the owner token when we created the game.
+    token: Option<String>,
+    spectator_token: Option<String>,
+    state: solitaire_backend::PlayerView,
+    version: u64,
+    updates: Updates,
+    /// The state as rebuilt from the updates, which mostly carry deltas.
+    synced: SyncedState,
+    /// A full state was asked for, updates failing to apply until it comes are skipped. Only set
+    /// once the request is sent, so that `next_update` may be cancelled at any point.
+    resyncing: bool,
+    /// Progress of the other players when the game is played in a match.
+    race: Option<tonic::Streaming<solitaire_grpc::proto::WatchMatchResponse>>,
+    match_id: Option<String>,
 }
 
 #[derive(Debug)]
 pub enum NewGameError {
     ConnectError(tonic::transport::Error),
     CreateGameError(tonic::Status),
+    GetGameError(tonic::Status),
+    WatchError(tonic::Status),
+    InvalidToken(ProtoError),
     NoState,
+    InvalidState(ProtoError),
+}
+
+/// How moves are sent and updates received.
+enum Updates {
+    /// Moves are played with `Act`, updates come from `Watch`. Servers without `Play` sessions,
+    /// and spectators, go this way.
+    Watch(tonic::Streaming<solitaire_grpc::proto::WatchResponse>),
+    /// Moves and updates go through one `Play` session.
+    Play(PlaySession),
+}
+
+struct PlaySession {
+    requests: mpsc::Sender<solitaire_grpc::proto::PlayRequest>,
+    responses: tonic::Streaming<solitaire_grpc::proto::PlayResponse>,
+    /// Updates received while waiting for the result of a move.
+    pending: VecDeque<solitaire_grpc::proto::WatchResponse>,
+    last_request_id: u64,
+}
+
+fn session_closed() -> tonic::Status {
+    tonic::Status::unavailable("Play session closed")
+}
+
+/// Whether a call failed because the server could not be reached, or is restarting, rather than
+/// because it refused the call.
+fn connection_lost(status: &tonic::Status) -> bool {
+    match status.code() {
+        tonic::Code::Unavailable => true,
+        // Transport errors of calls already made, such as a reset connection
+        tonic::Code::Unknown => std::error::Error::source(status).is_some(),
+        _ => false,
+    }
+}
+
+/// Sent with a move so that sending it again cannot play it twice.
+fn idempotency_key() -> String {
+    let mut random = DefaultRandomEngine::new();
+    format!("{:016x}{:016x}", random.next(), random.next())
+}
+
+fn status_of(error: NewGameError) -> tonic::Status {
+    match error {
+        NewGameError::ConnectError(e) => tonic::Status::unavailable(e.to_string()),
+        NewGameError::CreateGameError(status)
+        | NewGameError::GetGameError(status)
+        | NewGameError::WatchError(status) => status,
+        NewGameError::InvalidToken(e) => tonic::Status::invalid_argument(e.to_string()),
+        NewGameError::NoState => tonic::Status::internal("Missing game state"),
+        NewGameError::InvalidState(e) => invalid_state(e),
+    }
+}
+
+/// States are checked as they are received, the game then reads them without failing.
+fn invalid_state(error: ProtoError) -> tonic::Status {
+    tonic::Status::internal(format!("Invalid state from the server: {error}"))
+}
+
+impl PlaySession {
+    /// Opens a session on game `id`, failing with UNIMPLEMENTED when the server has none.
+    async fn open(
+        client: &mut SolitaireClient<tonic::transport::Channel>,
+        id: &str,
+        token: &str,
+    ) -> Result<Self, NewGameError> {
+        let (requests, rx) = mpsc::channel(PLAY_CHANNEL_SIZE);
+        let open = play_request::Request::Open(play_request::Open {
+            id: id.to_owned(),
+            from_sequence: None,
+            deltas: true,
+        });
+        // The receiver is still here, so sending cannot fail
+        let _ = requests
+            .send(solitaire_grpc::proto::PlayRequest {
+                request: Some(open),
+            })
+            .await;
+        let request = new_request(ReceiverStream::new(rx), Some(token))
+            .map_err(NewGameError::InvalidToken)?;
+        let responses = client
+            .play(request)
+            .await
+            .map_err(NewGameError::WatchError)?
+            .into_inner();
+        Ok(Self {
+            requests,
+            responses,
+            pending: VecDeque::new(),
+            last_request_id: 0,
+        })
+    }
+
+    async fn next_update(
+        &mut self,
+    ) -> Result<Option<solitaire_grpc::proto::WatchResponse>, tonic::Status> {
+        if let Some(update) = self.pending.pop_front() {
+            return Ok(Some(update));
+        }
+        loop {
+            match self.responses.message().await? {
+                None => return Ok(None),
+                Some(solitaire_grpc::proto::PlayResponse {
+                    event: Some(play_response::Event::Update(update)),
+                }) => return Ok(Some(update)),
+                // Results are only awaited by their moves
+                Some(_) => (),
+            }
+        }
+    }
+
+    /// Asks for the full state, which comes after the updates already on their way. Those are
+    /// stale, the ones kept are dropped.
+    async fn snapshot(&mut self) -> Result<(), tonic::Status> {
+        let snapshot = play_request::Request::Snapshot(play_request::Snapshot {});
+        self.requests
+            .send(solitaire_grpc::proto::PlayRequest {
+                request: Some(snapshot),
+            })
+            .await
+            .map_err(|_| session_closed())?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Plays a move and waits for its result, keeping the updates received in the meantime.
+    async fn play(
+        &mut self,
+        action: solitaire_grpc::proto::Action,
+        expected_version: u64,
+        idempotency_key: &str,
+    ) -> Result<solitaire_grpc::proto::ActResponse, tonic::Status> {
+        self.last_request_id += 1;
+        let request_id = self.last_request_id;
+        let play = play_request::Request::Move(play_request::Move {
+            request_id,
+            action: Some(action),
+            expected_version: Some(expected_version),
+            idempotency_key: idempotency_key.to_owned(),
+        });
+        self.requests
+            .send(solitaire_grpc::proto::PlayRequest {
+                request: Some(play),
+            })
+            .await
+            .map_err(|_| session_closed())?;
+        loop {
+            match self.responses.message().await?.and_then(|r| r.event) {
+                None => return Err(session_closed()),
+                Some(play_response::Event::Update(update)) => self.pending.push_back(update),
+                Some(play_response::Event::Result(result)) if result.request_id == request_id => {
+                    return match result.outcome {
+                        None => Err(tonic::Status::internal("Missing move outcome")),
+                        Some(move_result::Outcome::Played(response)) => Ok(response),
+                        Some(move_result::Outcome::Refused(refused)) => Err(tonic::Status::new(
+                            tonic::Code::from_i32(refused.code),
+                            refused.message,
+                        )),
+                    };
+                }
+                Some(play_response::Event::Result(_)) => (),
+            }
+        }
+    }
+}
+
+impl Updates {
+    async fn next(
+        &mut self,
+    ) -> Result<Option<solitaire_grpc::proto::WatchResponse>, tonic::Status> {
+        match self {
+            Updates::Watch(updates) => updates.message().await,
+            Updates::Play(session) => session.next_update().await,
+        }
+    }
+}
+
+pub enum GameUpdate {
+    /// The game state changed, with the action that caused it when known.
+    Changed(Option<solitaire_backend::Action>),
+    /// A player of the match moved or joined, or the match was won.
+    Race(solitaire_grpc::proto::WatchMatchResponse),
+    /// The server closed the game, for the given reason.
+    Ended(String),
+}
+
+/// Address of a server, and the TLS settings of the connections to it. Servers given as https URLs
+/// without settings are checked against the root certificates of the system.
+#[derive(Clone)]
+pub struct Server {
+    addr: String,
+    tls: Option<tonic::transport::ClientTlsConfig>,
+}
+
+impl Server {
+    pub fn new(addr: String, tls: Option<tonic::transport::ClientTlsConfig>) -> Self {
+        Self { addr, tls }
+    }
+
+    async fn connect(
+        &self,
+    ) -> Result<SolitaireClient<tonic::transport::Channel>, tonic::transport::Error> {
+        let mut endpoint = tonic::transport::Endpoint::from_shared(self.addr.clone())?;
+        if let Some(tls) = &self.tls {
+            endpoint = endpoint.tls_config(tls.clone())?;
+        }
+        Ok(SolitaireClient::new(endpoint.connect().await?))
+    }
+}
+
+/// A registered player, with the token proving requests naming them come from them.
+pub struct Player {
+    pub name: String,
+    pub token: String,
+}
+
+fn player_request<T>(message: T, player: &Player) -> Result<tonic::Request<T>, NewGameError> {
+    let mut request = tonic::Request::new(message);
+    solitaire_grpc::authorize_player(&mut request, &player.token)
+        .map_err(NewGameError::InvalidToken)?;
+    Ok(request)
+}
+
+fn new_request<T>(message: T, token: Option<&str>) -> Result<tonic::Request<T>, ProtoError> {
+    let mut request = tonic::Request::new(message);
+    if let Some(token) = token {
+        solitaire_grpc::authorize(&mut request, token)?;
+    }
+    Ok(request)
+}
+
+/// The watch stream of a game ends with the reason it was closed, not found when it was destroyed
+/// or expired, unavailable when the server is shutting down.
+fn closed(status: tonic::Status) -> Result<GameUpdate, tonic::Status> {
+    match status.code() {
+        tonic::Code::NotFound | tonic::Code::Unavailable => {
+            Ok(GameUpdate::Ended(status.message().to_owned()))
+        }
+        _ => Err(status),
+    }
 }
 
 impl GrpcGame {
-    pub async fn new(addr: String) -> Result<Self, NewGameError> {
-        let mut client = SolitaireClient::connect(addr)
+    pub async fn new(
+        server: Server,
+        options: solitaire_backend::GameOptions,
+        private: bool,
+        player: Option<Player>,
+    ) -> Result<Self, NewGameError> {
+        let mut client = server.connect().await.map_err(NewGameError::ConnectError)?;
+        let message = solitaire_grpc::proto::CreateGameRequest {
+            config: Some((&options).into()),
+            require_spectator_token: private,
+            player: player
+                .as_ref()
+                .map(|player| player.name.clone())
+                .unwrap_or_default(),
+        };
+        let request = match &player {
+            None => tonic::Request::new(message),
+            Some(player) => player_request(message, player)?,
+        };
+        let response = client
+            .create_game(request)
+            .await
+            .map_err(NewGameError::CreateGameError)?
+            .into_inner();
+        Self::created(client, response).await
+    }
+
+    /// Starts the daily game of `player`, dealt the same for every player.
+    pub async fn new_daily(server: Server, player: Player) -> Result<Self, NewGameError> {
+        let mut client = server.connect().await.map_err(NewGameError::ConnectError)?;
+        let response = client
+            .create_daily_game(player_request(
+                solitaire_grpc::proto::CreateDailyGameRequest {
+                    player: player.name.clone(),
+                },
+                &player,
+            )?)
+            .await
+            .map_err(NewGameError::CreateGameError)?
+            .into_inner();
+        Self::created(client, response).await
+    }
+
+    /// Starts a match on a new deal, other players join it with its id.
+    pub async fn new_race(
+        server: Server,
+        options: solitaire_backend::GameOptions,
+        player: Player,
+    ) -> Result<Self, NewGameError> {
+        let mut client = server.connect().await.map_err(NewGameError::ConnectError)?;
+        let response = client
+            .create_match(player_request(
+                solitaire_grpc::proto::CreateMatchRequest {
+                    config: Some((&options).into()),
+                    player: player.name.clone(),
+                },
+                &player,
+            )?)
             .await
-            .map_err(|e| NewGameError::ConnectError(e))?;
+            .map_err(NewGameError::CreateGameError)?
+            .into_inner();
+        Self::raced(client, response.match_id, response.game).await
+    }
+
+    /// Joins the match `match_id`, on a game of our own dealt like the other ones.
+    pub async fn join_race(
+        server: Server,
+        match_id: String,
+        player: Player,
+    ) -> Result<Self, NewGameError> {
+        let mut client = server.connect().await.map_err(NewGameError::ConnectError)?;
         let response = client
-            .create_game(tonic::Request::new(
-                solitaire_grpc::proto::CreateGameRequest {},
+            .join_match(player_request(
+                solitaire_grpc::proto::JoinMatchRequest {
+                    match_id: match_id.clone(),
+                    player: player.name.clone(),
+                },
+                &player,
+            )?)
+            .await
+            .map_err(NewGameError::CreateGameError)?
+            .into_inner();
+        Self::raced(client, match_id, response.game).await
+    }
+
+    async fn raced(
+        mut client: SolitaireClient<tonic::transport::Channel>,
+        match_id: String,
+        response: Option<solitaire_grpc::proto::CreateGameResponse>,
+    ) -> Result<Self, NewGameError> {
+        let race = client
+            .watch_match(tonic::Request::new(
+                solitaire_grpc::proto::WatchMatchRequest {
+                    match_id: match_id.clone(),
+                },
             ))
             .await
-            .map_err(|e| NewGameError::CreateGameError(e))?
+            .map_err(NewGameError::WatchError)?
             .into_inner();
+        let mut game = Self::created(client, response.ok_or(NewGameError::NoState)?).await?;
+        game.race = Some(race);
+        game.match_id = Some(match_id);
+        Ok(game)
+    }
+
+    async fn created(
+        client: SolitaireClient<tonic::transport::Channel>,
+        response: solitaire_grpc::proto::CreateGameResponse,
+    ) -> Result<Self, NewGameError> {
+        let mut game = Self::subscribe(
+            client,
+            response.id,
+            Some(response.owner_token),
+            response.state,
+            response.version,
+        )
+        .await?;
+        game.spectator_token = response.spectator_token;
+        Ok(game)
+    }
+
+    /// Joins an existing game, `token` is needed to play it or to watch a private game.
+    pub async fn join(
+        server: Server,
+        id: String,
+        token: Option<String>,
+    ) -> Result<Self, NewGameError> {
+        let mut client = server.connect().await.map_err(NewGameError::ConnectError)?;
+        let request = new_request(
+            solitaire_grpc::proto::GetGameRequest { id: id.clone() },
+            token.as_deref(),
+        )
+        .map_err(NewGameError::InvalidToken)?;
+        let response = client
+            .get_game(request)
+            .await
+            .map_err(NewGameError::GetGameError)?
+            .into_inner();
+        Self::subscribe(client, id, token, response.state, response.version).await
+    }
+
+    /// Plays the game in a `Play` session when we may play it and the server supports them, or
+    /// watches it otherwise.
+    async fn subscribe(
+        mut client: SolitaireClient<tonic::transport::Channel>,
+        id: String,
+        token: Option<String>,
+        state: Option<solitaire_grpc::proto::State>,
+        version: u64,
+    ) -> Result<Self, NewGameError> {
+        let updates = Self::open_updates(&mut client, &id, token.as_deref()).await?;
         Ok(Self {
             client,
-            id: response.id,
-            state: match response.state {
-                None => Err(NewGameError::NoState),
-                Some(state) => Ok(state),
-            }?,
+            id,
+            token,
+            spectator_token: None,
+            state: (&state.ok_or(NewGameError::NoState)?)
+                .try_into()
+                .map_err(NewGameError::InvalidState)?,
+            version,
+            updates,
+            synced: SyncedState::default(),
+            resyncing: false,
+            race: None,
+            match_id: None,
+        })
+    }
+
+    async fn open_updates(
+        client: &mut SolitaireClient<tonic::transport::Channel>,
+        id: &str,
+        token: Option<&str>,
+    ) -> Result<Updates, NewGameError> {
+        let session = match token {
+            None => None,
+            Some(token) => match PlaySession::open(client, id, token).await {
+                Ok(session) => Some(session),
+                // Older servers, and spectators, only watch
+                Err(NewGameError::WatchError(status))
+                    if matches!(
+                        status.code(),
+                        tonic::Code::Unimplemented | tonic::Code::PermissionDenied
+                    ) =>
+                {
+                    None
+                }
+                Err(e) => return Err(e),
+            },
+        };
+        Ok(match session {
+            Some(session) => Updates::Play(session),
+            None => Updates::Watch(Self::watch(client, id, token).await?),
         })
     }
 
+    async fn watch(
+        client: &mut SolitaireClient<tonic::transport::Channel>,
+        id: &str,
+        token: Option<&str>,
+    ) -> Result<tonic::Streaming<solitaire_grpc::proto::WatchResponse>, NewGameError> {
+        let request = new_request(
+            solitaire_grpc::proto::WatchRequest {
+                id: id.to_owned(),
+                from_sequence: None,
+                deltas: true,
+            },
+            token,
+        )
+        .map_err(NewGameError::InvalidToken)?;
+        Ok(client
+            .watch(request)
+            .await
+            .map_err(NewGameError::WatchError)?
+            .into_inner())
+    }
+
+    /// Asks for the full state again once an update failed to apply: in the session, or by
+    /// watching the game anew.
+    async fn resync(&mut self) -> Result<(), tonic::Status> {
+        match &mut self.updates {
+            Updates::Play(session) => session.snapshot().await?,
+            Updates::Watch(updates) => {
+                *updates = Self::watch(&mut self.client, &self.id, self.token.as_deref())
+                    .await
+                    .map_err(status_of)?;
+            }
+        }
+        self.resyncing = true;
+        Ok(())
+    }
+
+    /// Reaches the server again once it was lost, waiting longer after each failed attempt. The
+    /// channel connects again by itself, the game is fetched and its updates opened anew.
+    async fn reconnect(&mut self) -> Result<(), tonic::Status> {
+        let mut delay = FIRST_RECONNECT_DELAY;
+        let mut attempt = 1;
+        loop {
+            match self.reopen().await {
+                Err(status)
+                    if attempt < RECONNECT_ATTEMPTS
+                        && (connection_lost(&status)
+                            || solitaire_grpc::retry_delay(&status).is_some()) =>
+                {
+                    tokio::time::sleep(solitaire_grpc::retry_delay(&status).unwrap_or(delay)).await;
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Everything is fetched before the game is changed, which is then changed all at once: a
+    /// reopening cancelled halfway leaves the game as it was.
+    async fn reopen(&mut self) -> Result<(), tonic::Status> {
+        let request = new_request(
+            solitaire_grpc::proto::GetGameRequest {
+                id: self.id.clone(),
+            },
+            self.token.as_deref(),
+        )
+        .map_err(|e| status_of(NewGameError::InvalidToken(e)))?;
+        let response = self.client.get_game(request).await?.into_inner();
+        let state = response
+            .state
+            .ok_or_else(|| status_of(NewGameError::NoState))?;
+        let state = (&state).try_into().map_err(invalid_state)?;
+        let updates = Self::open_updates(&mut self.client, &self.id, self.token.as_deref())
+            .await
+            .map_err(status_of)?;
+        let race = match (&self.race, &self.match_id) {
+            (Some(_), Some(match_id)) => {
+                let request = solitaire_grpc::proto::WatchMatchRequest {
+                    match_id: match_id.clone(),
+                };
+                // Matches do not outlive the server, the game goes on without them
+                match self.client.watch_match(tonic::Request::new(request)).await {
+                    Ok(response) => Some(response.into_inner()),
+                    Err(_) => None,
+                }
+            }
+            _ => self.race.take(),
+        };
+        self.updates = updates;
+        self.race = race;
+        self.state = state;
+        self.version = response.version;
+        // Sequences start over when the server restarts
+        self.synced = SyncedState::default();
+        self.resyncing = false;
+        Ok(())
+    }
+
+    async fn send_move(
+        &mut self,
+        action: &solitaire_backend::Action,
+        expected_version: u64,
+        idempotency_key: &str,
+    ) -> Result<solitaire_grpc::proto::ActResponse, tonic::Status> {
+        match &mut self.updates {
+            Updates::Play(session) => {
+                session
+                    .play(action.clone().into(), expected_version, idempotency_key)
+                    .await
+            }
+            Updates::Watch(_) => {
+                let request = new_request(
+                    solitaire_grpc::proto::ActRequest {
+                        id: self.id.clone(),
+                        action: Some(action.clone().into()),
+                        expected_version: Some(expected_version),
+                        idempotency_key: idempotency_key.to_owned(),
+                    },
+                    self.token.as_deref(),
+                )
+                .map_err(|e| status_of(NewGameError::InvalidToken(e)))?;
+                self.client
+                    .act(request)
+                    .await
+                    .map(tonic::Response::into_inner)
+            }
+        }
+    }
+
+    /// Waits until the game moves past the version we know, which happens when another client
+    /// acts on it. Our own moves are echoed back by the server and skipped here. Cancel safe, the
+    /// game is given up for the player's input whenever it comes first.
+    pub async fn next_update(&mut self) -> Result<GameUpdate, tonic::Status> {
+        loop {
+            let update = match self.race.as_mut() {
+                None => self.updates.next().await,
+                Some(race) => tokio::select! {
+                    update = self.updates.next() => update,
+                    progress = race.message() => {
+                        match progress? {
+                            // The match stream ends once the winner is known
+                            None => self.race = None,
+                            Some(progress) => return Ok(GameUpdate::Race(progress)),
+                        }
+                        continue;
+                    }
+                },
+            };
+            let update = match update {
+                Ok(update) => update,
+                Err(status) if connection_lost(&status) => match self.reconnect().await {
+                    Ok(()) => continue,
+                    Err(_) => return closed(status),
+                },
+                Err(status) => return closed(status),
+            };
+            let response = match update {
+                None => return Ok(GameUpdate::Ended("Game ended".to_owned())),
+                Some(response) => response,
+            };
+            match self.synced.apply(&response) {
+                Ok(false) => continue,
+                Ok(true) => self.resyncing = false,
+                Err(OutOfSync) if self.resyncing => continue,
+                Err(OutOfSync) => match self.resync().await {
+                    Ok(()) => continue,
+                    Err(status) => return closed(status),
+                },
+            }
+            match self.synced.state() {
+                Some(state) if response.version > self.version => {
+                    self.state = state.try_into().map_err(invalid_state)?;
+                    self.version = response.version;
+                    return Ok(GameUpdate::Changed(match response.action {
+                        None => None,
+                        Some(action) => Some((&action).try_into()?),
+                    }));
+                }
+                _ => (),
+            }
+        }
+    }
+
     pub fn id(&self) -> &str {
         self.id.as_str()
     }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    pub fn match_id(&self) -> Option<&str> {
+        self.match_id.as_deref()
+    }
+
+    /// Only known to the creator of a private game.
+    pub fn spectator_token(&self) -> Option<&str> {
+        self.spectator_token.as_deref()
+    }
+}
+
+#[derive(Debug)]
+pub enum QueryError {
+    ConnectError(tonic::transport::Error),
+    RequestError(tonic::Status),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::ConnectError(e) => write!(f, "{e}"),
+            QueryError::RequestError(status) => write!(f, "{}", status.message()),
+        }
+    }
+}
+
+/// Claims the name `player`, answering the token to play under it with.
+pub async fn register_player(server: Server, player: String) -> Result<String, QueryError> {
+    let mut client = server.connect().await.map_err(QueryError::ConnectError)?;
+    let response = client
+        .register_player(tonic::Request::new(
+            solitaire_grpc::proto::RegisterPlayerRequest { player },
+        ))
+        .await
+        .map_err(QueryError::RequestError)?;
+    Ok(response.into_inner().player_token)
+}
+
+pub async fn player_stats(
+    server: Server,
+    player: String,
+) -> Result<solitaire_grpc::proto::PlayerStats, QueryError> {
+    let mut client = server.connect().await.map_err(QueryError::ConnectError)?;
+    let response = client
+        .get_player_stats(tonic::Request::new(
+            solitaire_grpc::proto::GetPlayerStatsRequest {
+                player,
+                variant: None,
+                period: solitaire_grpc::proto::Period::AllTime.into(),
+            },
+        ))
+        .await
+        .map_err(QueryError::RequestError)?;
+    Ok(response.into_inner().stats.unwrap_or_default())
+}
+
+pub async fn leaderboard(
+    server: Server,
+    period: solitaire_grpc::proto::Period,
+) -> Result<Vec<solitaire_grpc::proto::PlayerStats>, QueryError> {
+    let mut client = server.connect().await.map_err(QueryError::ConnectError)?;
+    let response = client
+        .get_leaderboard(tonic::Request::new(
+            solitaire_grpc::proto::GetLeaderboardRequest {
+                variant: None,
+                period: period.into(),
+                limit: 0,
+            },
+        ))
+        .await
+        .map_err(QueryError::RequestError)?;
+    Ok(response.into_inner().entries)
+}
+
+pub async fn daily_results(
+    server: Server,
+    date: Option<String>,
+) -> Result<solitaire_grpc::proto::GetDailyResultsResponse, QueryError> {
+    let mut client = server.connect().await.map_err(QueryError::ConnectError)?;
+    let response = client
+        .get_daily_results(tonic::Request::new(
+            solitaire_grpc::proto::GetDailyResultsRequest { date },
+        ))
+        .await
+        .map_err(QueryError::RequestError)?;
+    Ok(response.into_inner())
 }
 
 #[async_trait]
 impl solitaire_backend::Game for GrpcGame {
     fn draw_pile_size(&self) -> usize {
-        self.state.draw_pile_size as usize
+        self.state.draw_pile_size
     }
 
     fn upturned(&self) -> Option<solitaire_backend::Card> {
-        self.state.upturned.as_ref().map(|c| c.try_into().unwrap())
+        self.state.upturned
     }
 
     fn foundations(&self) -> solitaire_backend::Foundations {
-        let mut foundations = solitaire_backend::Foundations::default();
-        for foundation in self.state.foundations.iter() {
-            foundations[solitaire_grpc::proto::Suite::from_i32(foundation.suite)
-                .ok_or_else(|| ProtoError::InvalidValue("foundation.suite".to_owned()))
-                .unwrap()
-                .try_into()
-                .unwrap()] = foundation.value.unwrap_or(0) as u8;
-        }
-        foundations
+        self.state.foundations
     }
 
-    fn tableaus<'a>(&'a self) -> Vec<solitaire_backend::Tableau> {
-        self.state
-            .tableaus
-            .iter()
-            .map(|t| solitaire_backend::Tableau {
-                downfaced_len: t.downfaced_len as usize,
-                upturned: t.upturned.iter().map(|c| c.try_into().unwrap()).collect(),
-            })
-            .collect()
+    fn tableaus(&self) -> Vec<solitaire_backend::Tableau> {
+        self.state.tableaus.clone()
     }
 
+    fn score(&self) -> Option<i32> {
+        self.state.score
+    }
+
+    /// Sends the move again, with the same idempotency key, when the server was lost while playing
+    /// it.
     async fn act(&mut self, action: solitaire_backend::Action) -> solitaire_backend::ActionResult {
-        let response = self
-            .client
-            .act(tonic::Request::new(solitaire_grpc::proto::ActRequest {
-                id: self.id.clone(),
-                action: Some(action.into()),
-            }))
-            .await;
+        let expected_version = self.version;
+        let key = idempotency_key();
+        let mut attempt = 1;
+        let response = loop {
+            match self.send_move(&action, expected_version, &key).await {
+                Err(status) if connection_lost(&status) && attempt < MOVE_ATTEMPTS => {
+                    attempt += 1;
+                    if let Err(status) = self.reconnect().await {
+                        break Err(status);
+                    }
+                }
+                response => break response,
+            }
+        };
         match response {
+            // The keys of the moves played are lost when the server restarts
+            Err(e) if e.code() == tonic::Code::Aborted && attempt > 1 => {
+                solitaire_backend::ActionResult::Failed(
+                    "The connection was lost, the move may have been played before".to_owned(),
+                )
+            }
+            Err(e) if e.code() == tonic::Code::Aborted => solitaire_backend::ActionResult::Failed(
+                "The game was changed by another player".to_owned(),
+            ),
+            Err(e)
+                if e.code() == tonic::Code::Unauthenticated
+                    || e.code() == tonic::Code::PermissionDenied =>
+            {
+                solitaire_backend::ActionResult::Failed(
+                    "Only the owner of the game may play, see --token".to_owned(),
+                )
+            }
             Err(e) => solitaire_backend::ActionResult::Failed(format!("{e}")),
             Ok(response) => {
-                let response = response.into_inner();
-                if let Some(state) = response.state {
-                    self.state = state;
+                // The move was played whatever its state holds, so its version is ours
+                self.version = response.version;
+                match response.state.as_ref().map(|state| state.try_into()) {
+                    Some(Ok(state)) => self.state = state,
+                    Some(Err(e)) => {
+                        return solitaire_backend::ActionResult::Failed(format!(
+                            "The server sent an unreadable state: {e}"
+                        ))
+                    }
+                    None => {
+                        return solitaire_backend::ActionResult::Failed(
+                            "The server sent no state".to_owned(),
+                        )
+                    }
                 }
                 if response.victory {
                     solitaire_backend::ActionResult::Victory
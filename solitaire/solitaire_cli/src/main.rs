@@ -1,39 +1,349 @@
-use boards::random_engine::DefaultRandomEngine;
+use async_trait::async_trait;
+use boards::random_engine::{DefaultRandomEngine, XorShifEngine};
 use solitaire_backend::*;
 use std::io::Write;
 use std::str::FromStr;
 use std::{env, fmt};
+use tokio::io::AsyncBufReadExt;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
 mod grpc;
-use grpc::{GrpcGame, NewGameError};
+use grpc::{GameUpdate, GrpcGame, NewGameError, Player, Server};
 
-pub trait DisplayableGame: Game + fmt::Display {}
+#[async_trait]
+pub trait DisplayableGame: Game + fmt::Display {
+    /// Waits for the game to be changed by someone other than the local player.
+    async fn next_update(&mut self) -> Result<GameUpdate, tonic::Status>;
+}
+
+#[async_trait]
+impl DisplayableGame for MemoryGame {
+    async fn next_update(&mut self) -> Result<GameUpdate, tonic::Status> {
+        std::future::pending().await
+    }
+}
 
-impl DisplayableGame for MemoryGame {}
-impl DisplayableGame for GrpcGame {}
+#[async_trait]
+impl DisplayableGame for GrpcGame {
+    async fn next_update(&mut self) -> Result<GameUpdate, tonic::Status> {
+        GrpcGame::next_update(self).await
+    }
+}
 
-fn new_memory_game() -> Box<dyn DisplayableGame> {
-    let mut rand = DefaultRandomEngine::new();
-    Box::new(MemoryGame::new(&mut rand))
+fn new_memory_game(options: GameOptions) -> Box<dyn DisplayableGame> {
+    let deck = match options.seed {
+        None => shuffled_deck(&mut DefaultRandomEngine::new()),
+        Some(seed) => shuffled_deck(&mut XorShifEngine::new(seed)),
+    };
+    Box::new(MemoryGame::with_options(deck, options))
 }
 
-async fn new_grpc_game(addr: String) -> Result<Box<dyn DisplayableGame>, NewGameError> {
-    GrpcGame::new(addr)
+async fn new_grpc_game(
+    server: Server,
+    options: GameOptions,
+    private: bool,
+    player: Option<Player>,
+) -> Result<Box<dyn DisplayableGame>, NewGameError> {
+    GrpcGame::new(server, options, private, player)
         .await
         .map(|g| -> Box<dyn DisplayableGame> {
             println!("Starting grpc game {}", g.id());
+            if let Some(token) = g.token() {
+                println!("Owner token: {}", token);
+            }
+            if let Some(token) = g.spectator_token() {
+                println!("Spectator token: {}", token);
+            }
+            Box::new(g)
+        })
+}
+
+async fn new_daily_grpc_game(
+    server: Server,
+    player: Player,
+) -> Result<Box<dyn DisplayableGame>, NewGameError> {
+    GrpcGame::new_daily(server, player)
+        .await
+        .map(|g| -> Box<dyn DisplayableGame> {
+            println!("Starting daily grpc game {}", g.id());
+            if let Some(token) = g.token() {
+                println!("Owner token: {}", token);
+            }
             Box::new(g)
         })
 }
 
+async fn new_race_grpc_game(
+    server: Server,
+    options: GameOptions,
+    player: Player,
+    match_id: Option<String>,
+) -> Result<Box<dyn DisplayableGame>, NewGameError> {
+    let game = match match_id {
+        None => GrpcGame::new_race(server, options, player).await,
+        Some(match_id) => GrpcGame::join_race(server, match_id, player).await,
+    };
+    game.map(|g| -> Box<dyn DisplayableGame> {
+        println!(
+            "Racing in match {} on grpc game {}",
+            g.match_id().unwrap_or_default(),
+            g.id()
+        );
+        if let Some(token) = g.token() {
+            println!("Owner token: {}", token);
+        }
+        Box::new(g)
+    })
+}
+
+async fn join_grpc_game(
+    server: Server,
+    id: String,
+    token: Option<String>,
+) -> Result<Box<dyn DisplayableGame>, NewGameError> {
+    GrpcGame::join(server, id, token)
+        .await
+        .map(|g| -> Box<dyn DisplayableGame> {
+            println!("Joining grpc game {}", g.id());
+            Box::new(g)
+        })
+}
+
+fn print_update(action: Option<Action>) {
+    if let Some(action) = action {
+        println!("Played: {}", action);
+    }
+}
+
+fn print_race(progress: &solitaire_grpc::proto::WatchMatchResponse) {
+    for racer in progress.racers.iter() {
+        println!(
+            "{: <32} {: >2}/52 cards on foundations, {} moves",
+            racer.player, racer.foundation_cards, racer.moves
+        );
+    }
+    if let Some(winner) = &progress.winner {
+        println!("{} won the race", winner);
+    }
+}
+
+async fn watch_grpc_game(server: Server, id: String, token: Option<String>) {
+    let mut game = match GrpcGame::join(server, id, token).await {
+        Ok(game) => game,
+        Err(e) => panic!("Failed to watch grpc game: {:?}", e),
+    };
+    println!("Watching grpc game {}", game.id());
+    println!("{}", game);
+
+    loop {
+        match game.next_update().await {
+            Ok(GameUpdate::Ended(reason)) => {
+                println!("{}", reason);
+                break;
+            }
+            Ok(GameUpdate::Changed(action)) => {
+                print_update(action);
+                println!("{}", game);
+            }
+            Ok(GameUpdate::Race(progress)) => print_race(&progress),
+            Err(e) => {
+                println!("Watch stream failed: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn parse_arg<T: FromStr>(name: &str, value: Option<String>) -> T {
+    match value.map(|v| v.parse()) {
+        None => panic!("{} was given without a value", name),
+        Some(Err(_)) => panic!("{} was given an invalid value", name),
+        Some(Ok(v)) => v,
+    }
+}
+
+fn print_stats(stats: &solitaire_grpc::proto::PlayerStats) {
+    let optional = |v: Option<String>| v.unwrap_or_else(|| "-".to_owned());
+    println!(
+        "{: <32} {: >6} {: >6} {: >8} {: >10} {: >10}",
+        stats.player,
+        stats.played,
+        stats.won,
+        optional(stats.best_score.map(|s| s.to_string())),
+        optional(stats.fastest_win_ms.map(|ms| format!("{}s", ms / 1000))),
+        optional(stats.fewest_moves.map(|m| m.to_string())),
+    );
+}
+
+fn print_stats_header() {
+    println!(
+        "{: <32} {: >6} {: >6} {: >8} {: >10} {: >10}",
+        "Player", "Played", "Won", "Best", "Fastest", "Fewest"
+    );
+}
+
+async fn register_player(server: Server, player: String) {
+    match grpc::register_player(server, player).await {
+        Ok(token) => println!("Player token: {}", token),
+        Err(e) => panic!("Failed to register player: {}", e),
+    }
+}
+
+async fn show_player_stats(server: Server, player: String) {
+    match grpc::player_stats(server, player).await {
+        Ok(stats) => {
+            print_stats_header();
+            print_stats(&stats);
+        }
+        Err(e) => panic!("Failed to get player stats: {}", e),
+    }
+}
+
+async fn show_leaderboard(server: Server, period: Option<String>) {
+    use solitaire_grpc::proto::Period;
+    let period = match period.as_deref() {
+        None | Some("all") => Period::AllTime,
+        Some("day") => Period::Day,
+        Some("week") => Period::Week,
+        Some("month") => Period::Month,
+        Some(p) => panic!("Unknown period {}, expected all, day, week or month", p),
+    };
+    match grpc::leaderboard(server, period).await {
+        Ok(entries) => {
+            print_stats_header();
+            for stats in entries.iter() {
+                print_stats(stats);
+            }
+        }
+        Err(e) => panic!("Failed to get leaderboard: {}", e),
+    }
+}
+
+async fn show_daily_results(server: Server, date: Option<String>) {
+    let response = match grpc::daily_results(server, date).await {
+        Ok(response) => response,
+        Err(e) => panic!("Failed to get daily results: {}", e),
+    };
+    println!("Daily game of {}", response.date);
+    println!(
+        "{: <32} {: >6} {: >6} {: >8} {: >10}",
+        "Player", "Result", "Moves", "Score", "Time"
+    );
+    for result in response.results.iter() {
+        println!(
+            "{: <32} {: >6} {: >6} {: >8} {: >10}",
+            result.player,
+            if result.won { "won" } else { "lost" },
+            result.moves,
+            result
+                .score
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "-".to_owned()),
+            format!("{}s", result.duration_ms / 1000),
+        );
+    }
+}
+
+fn read_file(arg: &str, path: Option<String>) -> Vec<u8> {
+    let path = parse_arg::<String>(arg, path);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+}
+
+/// Takes the TLS options out of `args`, they apply to every command talking to a server.
+fn take_tls_config(args: Vec<String>) -> (Vec<String>, Option<ClientTlsConfig>) {
+    let mut tls = None;
+    let mut cert = None;
+    let mut key = None;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--ca" {
+            let ca = Certificate::from_pem(read_file(&arg, args.next()));
+            tls = Some(tls.unwrap_or_else(ClientTlsConfig::new).ca_certificate(ca));
+        } else if arg == "--cert" {
+            cert = Some(read_file(&arg, args.next()));
+        } else if arg == "--key" {
+            key = Some(read_file(&arg, args.next()));
+        } else if arg == "--tls-domain" {
+            let domain = parse_arg::<String>(&arg, args.next());
+            tls = Some(tls.unwrap_or_else(ClientTlsConfig::new).domain_name(domain));
+        } else {
+            rest.push(arg);
+        }
+    }
+    match (cert, key) {
+        (None, None) => (),
+        (Some(cert), Some(key)) => {
+            let identity = Identity::from_pem(cert, key);
+            tls = Some(tls.unwrap_or_else(ClientTlsConfig::new).identity(identity));
+        }
+        _ => panic!("--cert and --key must be given together"),
+    }
+    (rest, tls)
+}
+
 enum GameOption {
     Memory,
-    Grpc(String),
+    Grpc(String, Option<String>),
+    Daily(String, Player),
+    Race(String, Player, Option<String>),
 }
 
 #[tokio::main]
 async fn main() {
-    let mut args = env::args().skip(1);
+    let (args, tls) = take_tls_config(env::args().skip(1).collect());
+    let server = |addr| Server::new(addr, tls.clone());
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(|arg| arg.as_str()) {
+        Some("watch") => {
+            args.next();
+            match (args.next(), args.next()) {
+                (Some(addr), Some(id)) => {
+                    return watch_grpc_game(server(addr), id, args.next()).await
+                }
+                _ => panic!("usage: solitaire_cli watch <server> <game-id> [token]"),
+            }
+        }
+        Some("register") => {
+            args.next();
+            match (args.next(), args.next()) {
+                (Some(addr), Some(player)) => return register_player(server(addr), player).await,
+                _ => panic!("usage: solitaire_cli register <server> <player>"),
+            }
+        }
+        Some("stats") => {
+            args.next();
+            match (args.next(), args.next()) {
+                (Some(addr), Some(player)) => return show_player_stats(server(addr), player).await,
+                _ => panic!("usage: solitaire_cli stats <server> <player>"),
+            }
+        }
+        Some("leaderboard") => {
+            args.next();
+            match args.next() {
+                Some(addr) => return show_leaderboard(server(addr), args.next()).await,
+                None => panic!("usage: solitaire_cli leaderboard <server> [all|day|week|month]"),
+            }
+        }
+        Some("daily") => {
+            args.next();
+            match args.next() {
+                Some(addr) => return show_daily_results(server(addr), args.next()).await,
+                None => panic!("usage: solitaire_cli daily <server> [YYYY-MM-DD]"),
+            }
+        }
+        _ => (),
+    }
+
     let mut game_option = None;
+    let mut join = None;
+    let mut token = None;
+    let mut private = false;
+    let mut player = None;
+    let mut player_token = None;
+    let mut daily = false;
+    let mut race = false;
+    let mut match_id = None;
+    let mut options = GameOptions::default();
     while let Some(arg) = args.next() {
         if arg == "--grpc" {
             match args.next() {
@@ -44,31 +354,136 @@ async fn main() {
                     if game_option.is_some() {
                         panic!("--grpc was given more than once");
                     }
-                    game_option = Some(GameOption::Grpc(addr));
+                    game_option = Some(addr);
                 }
             }
+        } else if arg == "--join" {
+            match args.next() {
+                None => {
+                    panic!("--join was given without a game id");
+                }
+                Some(id) => {
+                    if join.is_some() {
+                        panic!("--join was given more than once");
+                    }
+                    join = Some(id);
+                }
+            }
+        } else if arg == "--token" {
+            token = Some(parse_arg::<String>(&arg, args.next()));
+        } else if arg == "--private" {
+            private = true;
+        } else if arg == "--daily" {
+            daily = true;
+        } else if arg == "--race" {
+            race = true;
+        } else if arg == "--match" {
+            match_id = Some(parse_arg::<String>(&arg, args.next()));
+        } else if arg == "--player" {
+            player = Some(parse_arg::<String>(&arg, args.next()));
+        } else if arg == "--player-token" {
+            player_token = Some(parse_arg::<String>(&arg, args.next()));
+        } else if arg == "--variant" {
+            options.variant = parse_arg(&arg, args.next());
+        } else if arg == "--draw" {
+            options.draw_count = parse_arg(&arg, args.next());
+        } else if arg == "--redeals" {
+            options.redeal_limit = Some(parse_arg(&arg, args.next()));
+        } else if arg == "--scoring" {
+            options.scoring = parse_arg(&arg, args.next());
+        } else if arg == "--seed" {
+            options.seed = Some(parse_arg(&arg, args.next()));
+        } else if arg == "--auto-foundation" {
+            options.auto_foundation = true;
         }
     }
+    if let Err(e) = options.validate() {
+        panic!("{}", e);
+    }
+    if options.seed == Some(0) {
+        panic!("--seed must not be 0");
+    }
 
-    let game_option = game_option.unwrap_or(GameOption::Memory);
+    let mut player = match (player, player_token) {
+        (None, None) => None,
+        (Some(name), Some(token)) => Some(Player { name, token }),
+        (Some(_), None) => panic!("--player requires --player-token, given by `register`"),
+        (None, Some(_)) => panic!("--player-token requires --player"),
+    };
+
+    let race = race || match_id.is_some();
+    if race && (daily || join.is_some()) {
+        panic!("--race and --match cannot be combined with --daily or --join");
+    }
+    let game_option = match (game_option, join, daily) {
+        (None, None, false) if race => panic!("--race and --match require --grpc"),
+        (Some(addr), None, false) if race => match player.take() {
+            Some(player) => GameOption::Race(addr, player, match_id),
+            None => panic!("--race and --match require --player"),
+        },
+        (None, None, false) => GameOption::Memory,
+        (None, Some(_), _) => panic!("--join requires --grpc"),
+        (None, None, true) => panic!("--daily requires --grpc"),
+        (Some(_), Some(_), true) => panic!("--daily and --join cannot be combined"),
+        (Some(addr), None, true) => match player.take() {
+            Some(player) => GameOption::Daily(addr, player),
+            None => panic!("--daily requires --player"),
+        },
+        (Some(addr), join, false) => GameOption::Grpc(addr, join),
+    };
 
     let mut game = match game_option {
-        GameOption::Memory => new_memory_game(),
-        GameOption::Grpc(addr) => match new_grpc_game(addr).await {
+        GameOption::Memory => new_memory_game(options),
+        GameOption::Grpc(addr, None) => {
+            match new_grpc_game(server(addr), options, private, player).await {
+                Ok(game) => game,
+                Err(e) => panic!("Failed to create grpc game: {:?}", e),
+            }
+        }
+        GameOption::Grpc(addr, Some(id)) => match join_grpc_game(server(addr), id, token).await {
             Ok(game) => game,
-            Err(e) => panic!("Failed to create grpc game: {:?}", e),
+            Err(e) => panic!("Failed to join grpc game: {:?}", e),
         },
+        GameOption::Daily(addr, player) => match new_daily_grpc_game(server(addr), player).await {
+            Ok(game) => game,
+            Err(e) => panic!("Failed to create daily grpc game: {:?}", e),
+        },
+        GameOption::Race(addr, player, match_id) => {
+            match new_race_grpc_game(server(addr), options, player, match_id).await {
+                Ok(game) => game,
+                Err(e) => panic!("Failed to race: {:?}", e),
+            }
+        }
     };
 
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
     loop {
         println!("{}", game);
         println!("[0] [1] [2] [3] [4] [5] [6]");
         print!("> ");
         std::io::stdout().flush().unwrap();
-        let mut line = String::new();
-        if std::io::stdin().read_line(&mut line).is_err() {
-            break;
-        }
+        let line = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => line,
+                _ => break,
+            },
+            update = game.next_update() => {
+                println!();
+                match update {
+                    Ok(GameUpdate::Changed(action)) => {
+                        print_update(action);
+                        continue;
+                    }
+                    Ok(GameUpdate::Race(progress)) => {
+                        print_race(&progress);
+                        continue;
+                    }
+                    Ok(GameUpdate::Ended(reason)) => println!("{}", reason),
+                    Err(e) => println!("Watch stream failed: {}", e),
+                }
+                break;
+            }
+        };
 
         let line = line.trim();
 
@@ -1,4 +1,5 @@
-use std::env;
+use std::path::PathBuf;
+use std::{env, fs};
 
 const PROTOC_PATH: &str = "protoc-21.2-win64/bin";
 
@@ -12,6 +13,29 @@ fn main() -> std::io::Result<()> {
     env::set_var("PATH", path);
 
     let proto_file = "./proto/solitaire.proto";
+    println!("cargo:rerun-if-changed={}", proto_file);
+    for file in STANDARD_PROTOS {
+        println!("cargo:rerun-if-changed={}", file);
+    }
 
-    tonic_build::compile_protos(proto_file)
+    // The descriptors of every file are served by the reflection service
+    let descriptor_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join(DESCRIPTOR_SET);
+    let mut protos = vec![proto_file];
+    protos.extend(STANDARD_PROTOS);
+    tonic_build::configure()
+        .file_descriptor_set_path(&descriptor_path)
+        .compile(&protos, &["./proto"])?;
+
+    // Messages are carried as JSON following the proto3 mapping, under the `json` feature
+    pbjson_build::Builder::new()
+        .register_descriptors(&fs::read(descriptor_path)?)?
+        .build(&[".solitaire"])
 }
+
+const DESCRIPTOR_SET: &str = "solitaire_descriptor.bin";
+
+/// Standard details of failed calls.
+const STANDARD_PROTOS: [&str; 2] = [
+    "./proto/google/rpc/status.proto",
+    "./proto/google/rpc/error_details.proto",
+];
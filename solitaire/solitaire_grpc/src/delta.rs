@@ -0,0 +1,203 @@
+use crate::proto::{self, state_delta};
+use crate::ProtoError;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The checksum sent with updates, a 64-bit FNV-1a hash of the encoded state.
+pub fn state_checksum(state: &proto::State) -> u64 {
+    prost::Message::encode_to_vec(state)
+        .iter()
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+        })
+}
+
+fn common_prefix(a: &[proto::Card], b: &[proto::Card]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// The changes turning `from` into `to`, both states of the same game.
+pub fn state_delta(from: &proto::State, to: &proto::State) -> proto::StateDelta {
+    proto::StateDelta {
+        draw_pile_size: (from.draw_pile_size != to.draw_pile_size).then_some(to.draw_pile_size),
+        upturned: (from.upturned != to.upturned).then(|| state_delta::Upturned {
+            card: to.upturned.clone(),
+        }),
+        foundations: to
+            .foundations
+            .iter()
+            .filter(|foundation| !from.foundations.contains(foundation))
+            .cloned()
+            .collect(),
+        tableaus: to
+            .tableaus
+            .iter()
+            .enumerate()
+            .filter(|(index, tableau)| from.tableaus.get(*index) != Some(tableau))
+            .map(|(index, tableau)| {
+                let kept = from
+                    .tableaus
+                    .get(index)
+                    .map_or(0, |from| common_prefix(&from.upturned, &tableau.upturned));
+                state_delta::TableauChange {
+                    index: index as u32,
+                    downfaced_len: tableau.downfaced_len,
+                    kept: kept as u32,
+                    added: tableau.upturned[kept..].to_vec(),
+                }
+            })
+            .collect(),
+        score: if from.score != to.score {
+            to.score
+        } else {
+            None
+        },
+    }
+}
+
+/// Applies the changes of `delta` to `state`, failing when they do not fit it.
+pub fn apply_delta(state: &mut proto::State, delta: &proto::StateDelta) -> Result<(), ProtoError> {
+    if let Some(draw_pile_size) = delta.draw_pile_size {
+        state.draw_pile_size = draw_pile_size;
+    }
+    if let Some(upturned) = &delta.upturned {
+        state.upturned = upturned.card.clone();
+    }
+    for foundation in delta.foundations.iter() {
+        *state
+            .foundations
+            .iter_mut()
+            .find(|f| f.suite == foundation.suite)
+            .ok_or_else(|| ProtoError::InvalidValue("delta.foundations.suite".to_owned()))? =
+            foundation.clone();
+    }
+    for change in delta.tableaus.iter() {
+        let tableau = state
+            .tableaus
+            .get_mut(change.index as usize)
+            .ok_or_else(|| ProtoError::InvalidValue("delta.tableaus.index".to_owned()))?;
+        let kept = change.kept as usize;
+        if kept > tableau.upturned.len() {
+            return Err(ProtoError::InvalidValue("delta.tableaus.kept".to_owned()));
+        }
+        tableau.downfaced_len = change.downfaced_len;
+        tableau.upturned.truncate(kept);
+        tableau.upturned.extend(change.added.iter().cloned());
+    }
+    if delta.score.is_some() {
+        state.score = delta.score;
+    }
+    Ok(())
+}
+
+/// An update could not be applied: one was missed, or the state it led to does not match its
+/// checksum. Updates are only applied again from a full state, which the client asks for.
+#[derive(Debug, PartialEq)]
+pub struct OutOfSync;
+
+/// The state of a game as rebuilt from its updates, full states and deltas alike.
+#[derive(Debug, Default)]
+pub struct SyncedState {
+    state: Option<proto::State>,
+    sequence: u64,
+}
+
+impl SyncedState {
+    /// Unknown until a full state is received, and after an update failed to apply.
+    pub fn state(&self) -> Option<&proto::State> {
+        self.state.as_ref()
+    }
+
+    /// Applies an update, telling whether it was new. Updates already applied, which come again
+    /// after a snapshot, are skipped.
+    pub fn apply(&mut self, update: &proto::WatchResponse) -> Result<bool, OutOfSync> {
+        let next = match (&self.state, &update.state, &update.delta) {
+            (Some(_), _, _) if update.sequence <= self.sequence => return Ok(false),
+            (_, Some(state), _) => state.clone(),
+            (Some(state), None, Some(delta)) if update.sequence == self.sequence + 1 => {
+                let mut next = state.clone();
+                apply_delta(&mut next, delta).map_err(|_| self.lose_sync())?;
+                next
+            }
+            _ => return Err(self.lose_sync()),
+        };
+        if matches!(update.checksum, Some(checksum) if checksum != state_checksum(&next)) {
+            return Err(self.lose_sync());
+        }
+        self.state = Some(next);
+        self.sequence = update.sequence;
+        Ok(true)
+    }
+
+    fn lose_sync(&mut self) -> OutOfSync {
+        self.state = None;
+        OutOfSync
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solitaire_backend::{shuffled_deck, GameOptions, MemoryGame};
+
+    fn card(suite: proto::Suite, rank: u32) -> proto::Card {
+        proto::Card {
+            suite: suite.into(),
+            rank,
+        }
+    }
+
+    fn update(sequence: u64, state: &proto::State, delta: bool) -> proto::WatchResponse {
+        proto::WatchResponse {
+            action: None,
+            state: (!delta).then(|| state.clone()),
+            version: sequence,
+            sequence,
+            delta: None,
+            checksum: Some(state_checksum(state)),
+        }
+    }
+
+    #[test]
+    fn deltas_rebuild_the_state() {
+        let deck = shuffled_deck(&mut boards::random_engine::XorShifEngine::new(7));
+        let game = MemoryGame::with_options(deck, GameOptions::default());
+        let dealt: proto::State = (&game.player_view()).into();
+
+        // A card moves from the first tableau to the second, revealing the one under it
+        let mut moved = dealt.clone();
+        moved.tableaus[0].upturned.clear();
+        moved.tableaus[0].downfaced_len = 0;
+        moved.tableaus[1].downfaced_len -= 1;
+        moved.tableaus[1].upturned =
+            vec![card(proto::Suite::Clubs, 9), card(proto::Suite::Hearts, 8)];
+        moved.draw_pile_size -= 1;
+        moved.upturned = Some(card(proto::Suite::Spades, 1));
+        moved.foundations[0].value = Some(1);
+
+        let delta = state_delta(&dealt, &moved);
+        assert_eq!(delta.tableaus.len(), 2);
+        let mut applied = dealt.clone();
+        apply_delta(&mut applied, &delta).unwrap();
+        assert_eq!(applied, moved);
+        assert_eq!(state_delta(&moved, &moved), proto::StateDelta::default());
+
+        let mut synced = SyncedState::default();
+        let mut first = update(1, &moved, true);
+        first.delta = Some(delta.clone());
+        assert_eq!(synced.apply(&first), Err(OutOfSync));
+        assert_eq!(synced.apply(&update(1, &dealt, false)), Ok(true));
+        let mut second = update(2, &moved, true);
+        second.delta = Some(delta.clone());
+        assert_eq!(synced.apply(&second), Ok(true));
+        assert_eq!(synced.state(), Some(&moved));
+        assert_eq!(synced.apply(&second), Ok(false));
+
+        // A delta leading to another state than the server has is caught by the checksum
+        let mut third = update(3, &dealt, true);
+        third.delta = Some(proto::StateDelta::default());
+        assert_eq!(synced.apply(&third), Err(OutOfSync));
+        assert_eq!(synced.state(), None);
+    }
+}
@@ -1,16 +1,167 @@
+use boards::cards::french::{ACE, KING};
 use solitaire_backend::{
-    Action, Card, Foundation, FoundationSource, Game, MemoryGame, Suite, Tableau,
+    Action, Card, Foundation, FoundationSource, GameOptions, PlayerView, ScoringMode, Suite,
+    Tableau, Variant, TABLEAUS_COUNT,
 };
+use std::fmt;
 
 pub mod proto {
     tonic::include_proto!("solitaire");
+    #[cfg(feature = "json")]
+    include!(concat!(env!("OUT_DIR"), "/solitaire.serde.rs"));
 }
 
+mod delta;
+pub use delta::{apply_delta, state_checksum, state_delta, OutOfSync, SyncedState};
+mod state;
+
+/// Standard details of failed calls, which clients act on.
+pub mod rpc {
+    tonic::include_proto!("google.rpc");
+}
+
+/// Encoded `FileDescriptorSet` of the protocol files, for server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("solitaire_descriptor");
+
+/// A message that does not hold what its fields say, such as a card of rank 14.
 #[derive(Debug)]
 pub enum ProtoError {
+    /// The path of the field, `state.upturned.rank`.
     InvalidValue(String),
 }
 
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtoError::InvalidValue(field) => write!(f, "Invalid value of `{field}`"),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+const RETRY_INFO_TYPE: &str = "type.googleapis.com/google.rpc.RetryInfo";
+const BAD_REQUEST_TYPE: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+const AUTHORIZATION: &str = "authorization";
+const BEARER: &str = "Bearer ";
+const PLAYER_TOKEN: &str = "player-token";
+
+/// Sends a game token with a request, as a bearer token in its metadata.
+pub fn authorize<T>(request: &mut tonic::Request<T>, token: &str) -> Result<(), ProtoError> {
+    let value = format!("{}{}", BEARER, token)
+        .parse()
+        .map_err(|_| ProtoError::InvalidValue("token".to_owned()))?;
+    request.metadata_mut().insert(AUTHORIZATION, value);
+    Ok(())
+}
+
+/// Sends the token of the player a request names with it, in its metadata.
+pub fn authorize_player<T>(request: &mut tonic::Request<T>, token: &str) -> Result<(), ProtoError> {
+    let value = token
+        .parse()
+        .map_err(|_| ProtoError::InvalidValue("player_token".to_owned()))?;
+    request.metadata_mut().insert(PLAYER_TOKEN, value);
+    Ok(())
+}
+
+fn status_with_detail(
+    code: tonic::Code,
+    message: String,
+    type_url: &str,
+    detail: &impl prost::Message,
+) -> tonic::Status {
+    let details = rpc::Status {
+        code: code as i32,
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: type_url.to_owned(),
+            value: prost::Message::encode_to_vec(detail),
+        }],
+    };
+    tonic::Status::with_details(
+        code,
+        message,
+        prost::Message::encode_to_vec(&details).into(),
+    )
+}
+
+fn status_detail<T: prost::Message + Default>(status: &tonic::Status, type_url: &str) -> Option<T> {
+    let details: rpc::Status = prost::Message::decode(status.details()).ok()?;
+    let detail = details
+        .details
+        .iter()
+        .find(|any| any.type_url == type_url)?;
+    prost::Message::decode(&detail.value[..]).ok()
+}
+
+/// A status telling the client how long to wait before trying again, with a `RetryInfo`.
+pub fn retry_status(
+    code: tonic::Code,
+    message: impl Into<String>,
+    delay: std::time::Duration,
+) -> tonic::Status {
+    let retry_info = rpc::RetryInfo {
+        retry_delay: Some(delay.into()),
+    };
+    status_with_detail(code, message.into(), RETRY_INFO_TYPE, &retry_info)
+}
+
+/// The delay the server asked for before calling again, if any.
+pub fn retry_delay(status: &tonic::Status) -> Option<std::time::Duration> {
+    let retry_info: rpc::RetryInfo = status_detail(status, RETRY_INFO_TYPE)?;
+    retry_info.retry_delay?.try_into().ok()
+}
+
+/// An `INVALID_ARGUMENT` status naming every field of the request in error, with a `BadRequest`.
+pub fn bad_request(violations: Vec<rpc::bad_request::FieldViolation>) -> tonic::Status {
+    let message = violations
+        .iter()
+        .map(|violation| format!("`{}` {}", violation.field, violation.description))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let bad_request = rpc::BadRequest {
+        field_violations: violations,
+    };
+    status_with_detail(
+        tonic::Code::InvalidArgument,
+        format!("Invalid request: {message}"),
+        BAD_REQUEST_TYPE,
+        &bad_request,
+    )
+}
+
+/// The fields in error of a refused request, if the server named them.
+pub fn field_violations(status: &tonic::Status) -> Vec<rpc::bad_request::FieldViolation> {
+    status_detail::<rpc::BadRequest>(status, BAD_REQUEST_TYPE)
+        .map(|bad_request| bad_request.field_violations)
+        .unwrap_or_default()
+}
+
+/// Reads the game token a request was sent with, if any.
+pub fn request_token<T>(request: &tonic::Request<T>) -> Result<Option<&str>, ProtoError> {
+    match request.metadata().get(AUTHORIZATION) {
+        None => Ok(None),
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|v| v.strip_prefix(BEARER))
+            .map(Some)
+            .ok_or_else(|| ProtoError::InvalidValue(AUTHORIZATION.to_owned())),
+    }
+}
+
+/// Reads the player token a request was sent with, if any.
+pub fn request_player_token<T>(request: &tonic::Request<T>) -> Result<Option<&str>, ProtoError> {
+    match request.metadata().get(PLAYER_TOKEN) {
+        None => Ok(None),
+        Some(value) => value
+            .to_str()
+            .map(Some)
+            .map_err(|_| ProtoError::InvalidValue(PLAYER_TOKEN.to_owned())),
+    }
+}
+
 impl From<Suite> for proto::Suite {
     fn from(src: Suite) -> Self {
         match src {
@@ -22,20 +173,27 @@ impl From<Suite> for proto::Suite {
     }
 }
 
-impl TryInto<Suite> for proto::Suite {
+impl TryFrom<proto::Suite> for Suite {
     type Error = ProtoError;
 
-    fn try_into(self) -> Result<Suite, Self::Error> {
-        match self {
-            Self::Hearts => Ok(Suite::Hearts),
-            Self::Diamonds => Ok(Suite::Diamonds),
-            Self::Clubs => Ok(Suite::Clubs),
-            Self::Spades => Ok(Suite::Spades),
-            Self::Undefined => Err(ProtoError::InvalidValue(String::new())),
+    fn try_from(src: proto::Suite) -> Result<Self, Self::Error> {
+        match src {
+            proto::Suite::Hearts => Ok(Suite::Hearts),
+            proto::Suite::Diamonds => Ok(Suite::Diamonds),
+            proto::Suite::Clubs => Ok(Suite::Clubs),
+            proto::Suite::Spades => Ok(Suite::Spades),
+            proto::Suite::Undefined => Err(ProtoError::InvalidValue("suite".to_owned())),
         }
     }
 }
 
+/// Reads the suite of a message, `field` naming it in the error.
+pub fn suite_from_proto(suite: i32, field: &str) -> Result<Suite, ProtoError> {
+    proto::Suite::from_i32(suite)
+        .and_then(|suite| suite.try_into().ok())
+        .ok_or_else(|| ProtoError::InvalidValue(field.to_owned()))
+}
+
 pub fn suite_to_proto(suite: proto::Suite) -> i32 {
     suite.into()
 }
@@ -49,15 +207,16 @@ impl From<&Card> for proto::Card {
     }
 }
 
-impl TryInto<Card> for &proto::Card {
+impl TryFrom<&proto::Card> for Card {
     type Error = ProtoError;
 
-    fn try_into(self) -> Result<Card, Self::Error> {
+    fn try_from(src: &proto::Card) -> Result<Self, Self::Error> {
+        if !(ACE as u32..=KING as u32).contains(&src.rank) {
+            return Err(ProtoError::InvalidValue("card.rank".to_owned()));
+        }
         Ok(Card {
-            suite: proto::Suite::from_i32(self.suite)
-                .ok_or_else(|| ProtoError::InvalidValue("suite".to_owned()))?
-                .try_into()?,
-            rank: self.rank as u8,
+            suite: suite_from_proto(src.suite, "card.suite")?,
+            rank: src.rank as u8,
         })
     }
 }
@@ -83,17 +242,87 @@ impl From<&Tableau> for proto::Tableau {
     }
 }
 
-impl From<&MemoryGame> for proto::State {
-    fn from(src: &MemoryGame) -> Self {
+/// Only the view of a player is sent, never the game itself.
+impl From<&PlayerView> for proto::State {
+    fn from(src: &PlayerView) -> Self {
+        Self {
+            draw_pile_size: src.draw_pile_size as u32,
+            upturned: src.upturned.as_ref().map(|u| u.into()),
+            foundations: src.foundations.iter().map(|f| f.into()).collect(),
+            tableaus: src.tableaus.iter().map(|t| t.into()).collect(),
+            config: Some((&src.options).into()),
+            score: src.score,
+        }
+    }
+}
+
+impl From<Variant> for proto::Variant {
+    fn from(src: Variant) -> Self {
+        match src {
+            Variant::Klondike => Self::Klondike,
+            Variant::Thoughtful => Self::Thoughtful,
+        }
+    }
+}
+
+pub fn variant_from_proto(variant: i32) -> Option<Variant> {
+    match proto::Variant::from_i32(variant)? {
+        proto::Variant::Klondike => Some(Variant::Klondike),
+        proto::Variant::Thoughtful => Some(Variant::Thoughtful),
+    }
+}
+
+impl From<&GameOptions> for proto::GameConfig {
+    fn from(src: &GameOptions) -> Self {
         Self {
-            draw_pile_size: src.draw_pile_size() as u32,
-            upturned: src.upturned().as_ref().map(|u| u.into()),
-            foundations: src.foundations().iter().map(|f| f.into()).collect(),
-            tableaus: src.tableaus().iter().map(|t| t.into()).collect(),
+            variant: proto::Variant::from(src.variant).into(),
+            draw_count: src.draw_count as u32,
+            redeal_limit: src.redeal_limit,
+            scoring: match src.scoring {
+                ScoringMode::Unscored => proto::ScoringMode::Unscored,
+                ScoringMode::Standard => proto::ScoringMode::Standard,
+                ScoringMode::Vegas => proto::ScoringMode::Vegas,
+            }
+            .into(),
+            seed: src.seed,
+            auto_foundation: src.auto_foundation,
         }
     }
 }
 
+impl TryFrom<&proto::GameConfig> for GameOptions {
+    type Error = tonic::Status;
+
+    fn try_from(src: &proto::GameConfig) -> Result<Self, Self::Error> {
+        let options = GameOptions {
+            variant: variant_from_proto(src.variant)
+                .ok_or_else(|| tonic::Status::invalid_argument("Invalid `config.variant`"))?,
+            draw_count: match src.draw_count {
+                0 => 1,
+                n => n as usize,
+            },
+            redeal_limit: src.redeal_limit,
+            scoring: match proto::ScoringMode::from_i32(src.scoring) {
+                Some(proto::ScoringMode::Unscored) => ScoringMode::Unscored,
+                Some(proto::ScoringMode::Standard) => ScoringMode::Standard,
+                Some(proto::ScoringMode::Vegas) => ScoringMode::Vegas,
+                None => return Err(tonic::Status::invalid_argument("Invalid `config.scoring`")),
+            },
+            seed: src.seed,
+            auto_foundation: src.auto_foundation,
+        };
+        options
+            .validate()
+            .map_err(tonic::Status::invalid_argument)?;
+        if options.seed == Some(0) {
+            return Err(tonic::Status::invalid_argument(
+                "`config.seed` must not be 0",
+            ));
+        }
+        Ok(options)
+    }
+}
+
 impl From<Action> for proto::Action {
     fn from(src: Action) -> Self {
         proto::Action {
@@ -136,48 +365,102 @@ impl From<Action> for proto::Action {
     }
 }
 
-impl TryInto<Action> for &proto::Action {
+fn violation(field: &str, description: &str) -> rpc::bad_request::FieldViolation {
+    rpc::bad_request::FieldViolation {
+        field: field.to_owned(),
+        description: description.to_owned(),
+    }
+}
+
+/// Checks the index of a tableau named by an action.
+fn check_tableau(
+    index: u32,
+    field: &str,
+    violations: &mut Vec<rpc::bad_request::FieldViolation>,
+) -> usize {
+    if index as usize >= TABLEAUS_COUNT {
+        violations.push(violation(
+            field,
+            &format!("must be less than {TABLEAUS_COUNT}"),
+        ));
+    }
+    index as usize
+}
+
+/// Actions are checked against the dimensions of the game, the rules are left to the game: a
+/// move naming a tableau that does not exist is refused here, moving a card where it does not go
+/// is refused when playing it.
+impl TryFrom<&proto::Action> for Action {
     type Error = tonic::Status;
-    fn try_into(self) -> Result<Action, Self::Error> {
+    fn try_from(src: &proto::Action) -> Result<Self, Self::Error> {
         use proto::action::*;
-        match self
-            .action
-            .as_ref()
-            .ok_or_else(|| tonic::Status::invalid_argument("Missing field `action`"))?
-        {
-            Action::Draw(_) => Ok(solitaire_backend::Action::Draw),
-            Action::BuildFoundation(f) => {
+        let mut violations = Vec::new();
+        let action = match &src.action {
+            None => {
+                violations.push(violation("action", "is required"));
+                None
+            }
+            Some(Action::Draw(_)) => Some(solitaire_backend::Action::Draw),
+            Some(Action::BuildFoundation(f)) => {
                 use build_foundation::*;
-                Ok(solitaire_backend::Action::BuildFoundation {
-                    src: match f.source.as_ref().ok_or_else(|| {
-                        tonic::Status::invalid_argument(
-                            "Missing field `action.build_foundation.source`",
-                        )
-                    })? {
-                        Source::Upturned(_) => solitaire_backend::FoundationSource::Upturned,
-                        Source::Tableau(t) => {
-                            solitaire_backend::FoundationSource::Tableau(t.index as usize)
-                        }
-                    },
-                })
+                let src = match &f.source {
+                    None => {
+                        violations.push(violation("action.build_foundation.source", "is required"));
+                        None
+                    }
+                    Some(Source::Upturned(_)) => Some(FoundationSource::Upturned),
+                    Some(Source::Tableau(t)) => Some(FoundationSource::Tableau(check_tableau(
+                        t.index,
+                        "action.build_foundation.tableau.index",
+                        &mut violations,
+                    ))),
+                };
+                src.map(|src| solitaire_backend::Action::BuildFoundation { src })
             }
-            Action::BuildTableau(t) => {
+            Some(Action::BuildTableau(t)) => {
                 use build_tableau::*;
-                Ok(solitaire_backend::Action::BuildTableau {
-                    src: match t.source.as_ref().ok_or_else(|| {
-                        tonic::Status::invalid_argument(
-                            "Missing field `action.build_tableau.source`",
-                        )
-                    })? {
-                        Source::Upturned(_) => solitaire_backend::TableauSource::Upturned,
-                        Source::Tableau(t) => solitaire_backend::TableauSource::Tableau {
-                            index: t.index as usize,
-                            size: t.size as usize,
-                        },
-                    },
-                    dst: t.destination_index as usize,
-                })
+                let dst = check_tableau(
+                    t.destination_index,
+                    "action.build_tableau.destination_index",
+                    &mut violations,
+                );
+                let src = match &t.source {
+                    None => {
+                        violations.push(violation("action.build_tableau.source", "is required"));
+                        None
+                    }
+                    Some(Source::Upturned(_)) => Some(solitaire_backend::TableauSource::Upturned),
+                    Some(Source::Tableau(source)) => {
+                        let index = check_tableau(
+                            source.index,
+                            "action.build_tableau.tableau.index",
+                            &mut violations,
+                        );
+                        if index == dst {
+                            violations.push(violation(
+                                "action.build_tableau.destination_index",
+                                "must not be the source tableau",
+                            ));
+                        }
+                        // A tableau holds at most a run from a king down to an ace upturned
+                        if !(1..=KING as u32).contains(&source.size) {
+                            violations.push(violation(
+                                "action.build_tableau.tableau.size",
+                                &format!("must be between 1 and {KING}"),
+                            ));
+                        }
+                        Some(solitaire_backend::TableauSource::Tableau {
+                            index,
+                            size: source.size as usize,
+                        })
+                    }
+                };
+                src.map(|src| solitaire_backend::Action::BuildTableau { src, dst })
             }
+        };
+        match action {
+            Some(action) if violations.is_empty() => Ok(action),
+            _ => Err(bad_request(violations)),
         }
     }
 }
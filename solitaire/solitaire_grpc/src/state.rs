@@ -0,0 +1,122 @@
+use crate::{proto, suite_from_proto, ProtoError};
+use boards::cards::french::KING;
+use solitaire_backend::{Card, Foundations, PlayerView, Tableau, DECK_SIZE, TABLEAUS_COUNT};
+
+fn invalid(field: &str) -> ProtoError {
+    ProtoError::InvalidValue(field.to_owned())
+}
+
+fn card(src: &proto::Card, field: &str) -> Result<Card, ProtoError> {
+    src.try_into().map_err(|_| invalid(field))
+}
+
+impl TryFrom<&proto::Tableau> for Tableau {
+    type Error = ProtoError;
+
+    fn try_from(src: &proto::Tableau) -> Result<Self, Self::Error> {
+        if src.downfaced_len.saturating_add(src.upturned.len() as u64) > DECK_SIZE as u64 {
+            return Err(invalid("tableau.downfaced_len"));
+        }
+        Ok(Tableau {
+            downfaced_len: src.downfaced_len as usize,
+            upturned: src
+                .upturned
+                .iter()
+                .map(|c| card(c, "tableau.upturned"))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// A state received from the server is checked once as it is received, so that it is read without
+/// failing afterwards.
+impl TryFrom<&proto::State> for PlayerView {
+    type Error = ProtoError;
+
+    fn try_from(src: &proto::State) -> Result<Self, Self::Error> {
+        if src.draw_pile_size as usize > DECK_SIZE {
+            return Err(invalid("state.draw_pile_size"));
+        }
+        if src.tableaus.len() != TABLEAUS_COUNT {
+            return Err(invalid("state.tableaus"));
+        }
+        let mut foundations = Foundations::default();
+        for foundation in src.foundations.iter() {
+            let suite = suite_from_proto(foundation.suite, "state.foundations.suite")?;
+            match foundation.value.unwrap_or(0) {
+                value if value > KING as u32 => return Err(invalid("state.foundations.value")),
+                value => foundations[suite] = value as u8,
+            }
+        }
+        Ok(Self {
+            draw_pile_size: src.draw_pile_size as usize,
+            upturned: match &src.upturned {
+                None => None,
+                Some(upturned) => Some(card(upturned, "state.upturned")?),
+            },
+            foundations,
+            tableaus: src
+                .tableaus
+                .iter()
+                .map(|t| t.try_into())
+                .collect::<Result<_, _>>()?,
+            score: src.score,
+            options: match &src.config {
+                None => return Err(invalid("state.config")),
+                Some(config) => config.try_into().map_err(|_| invalid("state.config"))?,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solitaire_backend::{shuffled_deck, GameOptions, MemoryGame};
+
+    #[test]
+    fn malformed_states_are_refused() {
+        let deck = shuffled_deck(&mut boards::random_engine::XorShifEngine::new(7));
+        let game = MemoryGame::with_options(deck, GameOptions::default());
+        let dealt: proto::State = (&game.player_view()).into();
+        let state = PlayerView::try_from(&dealt).unwrap();
+        assert_eq!(state.tableaus.len(), TABLEAUS_COUNT);
+        assert_eq!(state.draw_pile_size, 24);
+
+        let refused = |change: fn(&mut proto::State)| {
+            let mut state = dealt.clone();
+            change(&mut state);
+            match PlayerView::try_from(&state) {
+                Ok(_) => panic!("A malformed state was accepted"),
+                Err(ProtoError::InvalidValue(field)) => field,
+            }
+        };
+        assert_eq!(
+            refused(|s| s.tableaus[3].upturned[0].rank = 14),
+            "tableau.upturned"
+        );
+        assert_eq!(
+            refused(|s| s.upturned = Some(proto::Card { suite: 9, rank: 1 })),
+            "state.upturned"
+        );
+        assert_eq!(
+            refused(|s| s.foundations[0].suite = 0),
+            "state.foundations.suite"
+        );
+        assert_eq!(
+            refused(|s| s.foundations[1].value = Some(14)),
+            "state.foundations.value"
+        );
+        assert_eq!(
+            refused(|s| {
+                s.tableaus.pop();
+            }),
+            "state.tableaus"
+        );
+        assert_eq!(
+            refused(|s| s.tableaus[0].downfaced_len = u64::MAX),
+            "tableau.downfaced_len"
+        );
+        assert_eq!(refused(|s| s.config = None), "state.config");
+    }
+}
@@ -0,0 +1,205 @@
+use uuid::Uuid;
+
+/// Token a request was sent with, extracted from its metadata by [`intercept`].
+#[derive(Clone)]
+pub struct GameToken(pub String);
+
+/// Player token a request was sent with, extracted from its metadata by [`intercept`].
+#[derive(Clone)]
+pub struct PlayerToken(pub String);
+
+/// Moves the bearer token and the player token of a request, if any, into its extensions, so
+/// services can check them against the game and the player the request is about.
+pub fn intercept(mut request: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+    extract_tokens(&mut request)?;
+    Ok(request)
+}
+
+/// What [`intercept`] does, for requests which do not go through the gRPC server.
+pub fn extract_tokens<T>(request: &mut tonic::Request<T>) -> Result<(), tonic::Status> {
+    let token = solitaire_grpc::request_token(request)
+        .map_err(|_| tonic::Status::unauthenticated("Malformed authorization metadata"))?
+        .map(|t| GameToken(t.to_owned()));
+    let player_token = solitaire_grpc::request_player_token(request)
+        .map_err(|_| tonic::Status::unauthenticated("Malformed player token metadata"))?
+        .map(|t| PlayerToken(t.to_owned()));
+    if let Some(token) = token {
+        request.extensions_mut().insert(token);
+    }
+    if let Some(token) = player_token {
+        request.extensions_mut().insert(token);
+    }
+    Ok(())
+}
+
+/// Checks that `request` was sent by `player`, with the token whose hash it registered.
+pub fn check_player<T>(
+    request: &tonic::Request<T>,
+    player: &str,
+    registered: Option<&TokenHash>,
+) -> Result<(), tonic::Status> {
+    let registered = registered.ok_or_else(|| {
+        tonic::Status::failed_precondition(format!("Player {player} is not registered"))
+    })?;
+    match request.extensions().get::<PlayerToken>() {
+        None => Err(tonic::Status::unauthenticated("Missing player token")),
+        Some(PlayerToken(token)) if &TokenHash::of(token) == registered => Ok(()),
+        Some(_) => Err(tonic::Status::permission_denied(format!(
+            "Invalid player token for {player}"
+        ))),
+    }
+}
+
+/// SHA-256 of a token, which is all the server keeps of it, so tokens cannot be read back from
+/// storage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenHash(String);
+
+impl TokenHash {
+    pub fn of(token: &str) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, token.as_bytes());
+        Self(digest.as_ref().iter().map(|b| format!("{b:02x}")).collect())
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() == 64 && hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')) {
+            Some(Self(hex.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_hex(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Secrets handed out when a game is created, only told to its creator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IssuedTokens {
+    pub owner: String,
+    pub spectator: Option<String>,
+}
+
+/// A new random token, of a game or a player.
+pub fn new_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+impl IssuedTokens {
+    pub fn new(private: bool) -> Self {
+        Self {
+            owner: new_token(),
+            spectator: if private { Some(new_token()) } else { None },
+        }
+    }
+
+    pub fn hashes(&self) -> GameTokens {
+        GameTokens {
+            owner: TokenHash::of(&self.owner),
+            spectator: self.spectator.as_deref().map(TokenHash::of),
+        }
+    }
+}
+
+/// What the server keeps of the tokens of a game. The owner may play and destroy the game, while
+/// watching it needs the spectator token when there is one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameTokens {
+    pub owner: TokenHash,
+    pub spectator: Option<TokenHash>,
+}
+
+impl GameTokens {
+    fn matches<T>(
+        request: &tonic::Request<T>,
+        expected: &[&TokenHash],
+    ) -> Result<(), tonic::Status> {
+        match request.extensions().get::<GameToken>() {
+            None => Err(tonic::Status::unauthenticated("Missing game token")),
+            Some(GameToken(token)) if expected.contains(&&TokenHash::of(token)) => Ok(()),
+            Some(_) => Err(tonic::Status::permission_denied("Invalid game token")),
+        }
+    }
+
+    pub fn check_owner<T>(&self, request: &tonic::Request<T>) -> Result<(), tonic::Status> {
+        Self::matches(request, &[&self.owner])
+    }
+
+    /// Anybody may watch a game without a spectator token, the owner always may.
+    pub fn check_spectator<T>(&self, request: &tonic::Request<T>) -> Result<(), tonic::Status> {
+        match &self.spectator {
+            None => Ok(()),
+            Some(spectator) => Self::matches(request, &[spectator, &self.owner]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intercept_extracts_bearer_token() {
+        let mut request = tonic::Request::new(());
+        solitaire_grpc::authorize(&mut request, "secret").unwrap();
+        let request = intercept(request).unwrap();
+        assert_eq!(
+            request
+                .extensions()
+                .get::<GameToken>()
+                .map(|t| t.0.as_str()),
+            Some("secret")
+        );
+
+        let request = intercept(tonic::Request::new(())).unwrap();
+        assert!(request.extensions().get::<GameToken>().is_none());
+        assert!(request.extensions().get::<PlayerToken>().is_none());
+
+        let mut request = tonic::Request::new(());
+        solitaire_grpc::authorize_player(&mut request, "alice-secret").unwrap();
+        let request = intercept(request).unwrap();
+        assert!(request.extensions().get::<GameToken>().is_none());
+        assert_eq!(
+            request
+                .extensions()
+                .get::<PlayerToken>()
+                .map(|t| t.0.as_str()),
+            Some("alice-secret")
+        );
+
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Basic secret".parse().unwrap());
+        let err = intercept(request).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[test]
+    fn tokens_are_checked_against_their_hashes() {
+        let issued = IssuedTokens::new(true);
+        let tokens = issued.hashes();
+        assert_ne!(tokens.owner.as_hex(), issued.owner);
+        assert_eq!(
+            TokenHash::from_hex(tokens.owner.as_hex()),
+            Some(tokens.owner.clone())
+        );
+        assert_eq!(TokenHash::from_hex(&issued.owner), None);
+
+        let request = |token: &str| {
+            let mut request = tonic::Request::new(());
+            request.extensions_mut().insert(GameToken(token.to_owned()));
+            request
+        };
+        assert!(tokens.check_owner(&request(&issued.owner)).is_ok());
+        let spectator = issued.spectator.as_deref().unwrap();
+        assert!(tokens.check_spectator(&request(spectator)).is_ok());
+        let err = tokens.check_owner(&request(spectator)).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = tokens
+            .check_owner(&request(tokens.owner.as_hex()))
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+}
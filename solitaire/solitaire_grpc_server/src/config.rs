@@ -0,0 +1,274 @@
+use crate::limits::RateLimit;
+use crate::registry::ServiceLimits;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing_subscriber::filter::LevelFilter;
+
+const DEFAULT_MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Certificate and key of the server, in PEM files. Clients must present a certificate signed by
+/// `client_ca` when it is given.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Settings of the server, read from a TOML file given with `--config`. Command-line arguments
+/// override the file.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub http_listen: SocketAddr,
+    pub storage: Option<PathBuf>,
+    /// Directory the games are written to when the server shuts down, to be loaded as storage on
+    /// the next start.
+    pub snapshot: Option<PathBuf>,
+    /// Origins of the pages allowed to call the server from browsers, no other origin is.
+    pub cors_origins: Vec<String>,
+    pub max_games: usize,
+    pub idle_timeout_secs: u64,
+    pub watch_channel_size: usize,
+    /// Calls of each client creating or joining games, told apart by their IP address.
+    pub create_rate: RateLimit,
+    /// Moves of each client.
+    pub act_rate: RateLimit,
+    /// Spectators of each game, the `Play` sessions of its player are not counted.
+    pub max_watchers: usize,
+    /// Size of the body of each request in bytes, over all the messages of streaming calls.
+    pub max_request_size: usize,
+    /// Key of the daily deals, which keeps them the same across restarts. A random one is used
+    /// when unset.
+    pub daily_secret: Option<String>,
+    #[serde(deserialize_with = "deserialize_level")]
+    pub log_level: LevelFilter,
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let limits = ServiceLimits::default();
+        Self {
+            listen: "[::1]:50051".parse().unwrap(),
+            http_listen: "[::1]:8080".parse().unwrap(),
+            storage: None,
+            snapshot: None,
+            cors_origins: Vec::new(),
+            max_games: limits.max_games,
+            idle_timeout_secs: limits.idle_timeout.as_secs(),
+            watch_channel_size: limits.watch_channel_size,
+            create_rate: limits.create_rate,
+            act_rate: limits.act_rate,
+            max_watchers: limits.max_watchers,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            daily_secret: None,
+            log_level: LevelFilter::INFO,
+            tls: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    MissingValue(String),
+    InvalidValue(String),
+    UnknownArgument(String),
+    ReadError(PathBuf, std::io::Error),
+    ParseError(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingValue(arg) => write!(f, "{arg} was given without a value"),
+            ConfigError::InvalidValue(arg) => write!(f, "{arg} was given an invalid value"),
+            ConfigError::UnknownArgument(arg) => write!(f, "Unknown argument {arg}"),
+            ConfigError::ReadError(path, e) => write!(f, "Failed to read {}: {e}", path.display()),
+            ConfigError::ParseError(path, e) => write!(f, "Invalid config {}: {e}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LevelFilter, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+fn parse_arg<T: FromStr>(name: &str, value: Option<String>) -> Result<T, ConfigError> {
+    match value.map(|v| v.parse()) {
+        None => Err(ConfigError::MissingValue(name.to_owned())),
+        Some(Err(_)) => Err(ConfigError::InvalidValue(name.to_owned())),
+        Some(Ok(v)) => Ok(v),
+    }
+}
+
+impl Config {
+    pub fn load(path: PathBuf) -> Result<Self, ConfigError> {
+        let text =
+            std::fs::read_to_string(&path).map_err(|e| ConfigError::ReadError(path.clone(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::ParseError(path, e))
+    }
+
+    /// Reads the config file given with `--config`, if any, then applies the other arguments.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, ConfigError> {
+        let args: Vec<_> = args.collect();
+        let mut config = match args.iter().position(|arg| arg == "--config") {
+            None => Config::default(),
+            Some(i) => Config::load(parse_arg("--config", args.get(i + 1).cloned())?)?,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = args.next();
+            match arg.as_str() {
+                "--config" => (),
+                "--listen" => config.listen = parse_arg(&arg, value)?,
+                "--http" => config.http_listen = parse_arg(&arg, value)?,
+                "--storage" => config.storage = Some(parse_arg(&arg, value)?),
+                "--snapshot" => config.snapshot = Some(parse_arg(&arg, value)?),
+                "--cors-origin" => config.cors_origins.push(parse_arg(&arg, value)?),
+                "--max-games" => config.max_games = parse_arg(&arg, value)?,
+                "--idle-timeout" => config.idle_timeout_secs = parse_arg(&arg, value)?,
+                "--watch-channel-size" => config.watch_channel_size = parse_arg(&arg, value)?,
+                "--create-rate" => config.create_rate.per_second = parse_arg(&arg, value)?,
+                "--create-burst" => config.create_rate.burst = parse_arg(&arg, value)?,
+                "--act-rate" => config.act_rate.per_second = parse_arg(&arg, value)?,
+                "--act-burst" => config.act_rate.burst = parse_arg(&arg, value)?,
+                "--max-watchers" => config.max_watchers = parse_arg(&arg, value)?,
+                "--max-request-size" => config.max_request_size = parse_arg(&arg, value)?,
+                "--daily-secret" => config.daily_secret = Some(parse_arg(&arg, value)?),
+                "--log-level" => config.log_level = parse_arg(&arg, value)?,
+                "--tls-cert" => config.tls_mut().cert = parse_arg(&arg, value)?,
+                "--tls-key" => config.tls_mut().key = parse_arg(&arg, value)?,
+                "--tls-client-ca" => config.tls_mut().client_ca = Some(parse_arg(&arg, value)?),
+                _ => return Err(ConfigError::UnknownArgument(arg)),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn tls_mut(&mut self) -> &mut TlsConfig {
+        self.tls.get_or_insert_with(TlsConfig::default)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.watch_channel_size == 0 {
+            return Err(ConfigError::InvalidValue("watch_channel_size".to_owned()));
+        }
+        if !self.create_rate.is_valid() {
+            return Err(ConfigError::InvalidValue("create_rate".to_owned()));
+        }
+        if !self.act_rate.is_valid() {
+            return Err(ConfigError::InvalidValue("act_rate".to_owned()));
+        }
+        if self.max_request_size == 0 {
+            return Err(ConfigError::InvalidValue("max_request_size".to_owned()));
+        }
+        if self.daily_secret.as_deref() == Some("") {
+            return Err(ConfigError::InvalidValue("daily_secret".to_owned()));
+        }
+        if let Some(tls) = &self.tls {
+            if tls.cert.as_os_str().is_empty() || tls.key.as_os_str().is_empty() {
+                return Err(ConfigError::InvalidValue(
+                    "tls, both a certificate and a key are needed".to_owned(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn limits(&self) -> ServiceLimits {
+        ServiceLimits {
+            max_games: self.max_games,
+            idle_timeout: Duration::from_secs(self.idle_timeout_secs),
+            watch_channel_size: self.watch_channel_size,
+            create_rate: self.create_rate,
+            act_rate: self.act_rate,
+            max_watchers: self.max_watchers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn arguments_override_the_config_file() {
+        let path =
+            std::env::temp_dir().join(format!("solitaire-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+                listen = "0.0.0.0:6000"
+                max_games = 10
+                log_level = "debug"
+
+                [act_rate]
+                per_second = 5
+                burst = 10
+
+                [tls]
+                cert = "server.pem"
+                key = "server.key"
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_args(args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--max-games",
+            "20",
+            "--tls-client-ca",
+            "ca.pem",
+        ]))
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.listen, "0.0.0.0:6000".parse().unwrap());
+        assert_eq!(config.max_games, 20);
+        assert_eq!(config.log_level, LevelFilter::DEBUG);
+        assert_eq!(
+            config.limits().act_rate,
+            RateLimit {
+                per_second: 5.0,
+                burst: 10,
+            }
+        );
+        assert_eq!(
+            config.watch_channel_size,
+            Config::default().watch_channel_size
+        );
+        assert_eq!(
+            config.tls,
+            Some(TlsConfig {
+                cert: "server.pem".into(),
+                key: "server.key".into(),
+                client_ca: Some("ca.pem".into()),
+            })
+        );
+
+        assert!(Config::from_args(args(&["--tls-cert", "server.pem"])).is_err());
+        assert!(Config::from_args(args(&["--watch-channel-size", "0"])).is_err());
+        assert!(Config::from_args(args(&["--create-rate", "0"])).is_err());
+        assert!(Config::from_args(args(&["--daily-secret", ""])).is_err());
+        assert!(Config::from_args(args(&["--unknown"])).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use crate::solitaire::GameResult;
+use chrono::{DateTime, NaiveDate, Utc};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use solitaire_backend::{GameOptions, ScoringMode};
+use std::time::SystemTime;
+
+/// Where the server learns which daily game is being played, so it can be replaced in tests.
+pub trait DateSource: Send + Sync {
+    fn today(&self) -> NaiveDate;
+}
+
+/// Days change at midnight UTC, so every player shares the same daily game.
+pub struct SystemDateSource;
+
+impl DateSource for SystemDateSource {
+    fn today(&self) -> NaiveDate {
+        DateTime::<Utc>::from(SystemTime::now()).date_naive()
+    }
+}
+
+/// Key of the seeds of daily deals. Seeds of custom games are chosen by their players, so without
+/// it anybody could deal the game of the day and try it out before playing it.
+pub struct DailySecret(hmac::Key);
+
+impl DailySecret {
+    pub fn new(secret: &[u8]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    /// A secret nobody knows, not even the next run of the server: daily deals then change
+    /// whenever it restarts.
+    pub fn random() -> Self {
+        let mut secret = [0; 32];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .expect("Failed to generate the daily secret");
+        Self::new(&secret)
+    }
+
+    fn seed(&self, date: NaiveDate) -> u64 {
+        let tag = hmac::sign(&self.0, date.to_string().as_bytes());
+        let seed = u64::from_le_bytes(tag.as_ref()[..8].try_into().unwrap());
+        // The shuffling engine never leaves a zero state
+        seed.max(1)
+    }
+
+    /// Daily games are plain Klondike with standard scoring, dealt from a seed derived from the
+    /// date.
+    pub fn daily_options(&self, date: NaiveDate) -> GameOptions {
+        GameOptions {
+            scoring: ScoringMode::Standard,
+            seed: Some(self.seed(date)),
+            ..GameOptions::default()
+        }
+    }
+}
+
+pub fn parse_date(date: &str) -> Result<NaiveDate, tonic::Status> {
+    date.parse().map_err(|_| {
+        tonic::Status::invalid_argument(format!("Invalid date {date}, expected YYYY-MM-DD"))
+    })
+}
+
+/// Ranks the daily results of `date`: winners first, then by score, moves and time.
+pub fn daily_results(
+    results: &[GameResult],
+    date: NaiveDate,
+) -> Vec<solitaire_grpc::proto::DailyResult> {
+    let mut daily: Vec<_> = results.iter().filter(|r| r.daily == Some(date)).collect();
+    daily.sort_by(|a, b| {
+        b.won
+            .cmp(&a.won)
+            .then(b.score.cmp(&a.score))
+            .then(a.moves.cmp(&b.moves))
+            .then(a.duration.cmp(&b.duration))
+    });
+    daily
+        .into_iter()
+        .map(|r| solitaire_grpc::proto::DailyResult {
+            player: r.player.clone(),
+            won: r.won,
+            moves: r.moves,
+            duration_ms: r.duration.as_millis() as u64,
+            score: r.score,
+        })
+        .collect()
+}
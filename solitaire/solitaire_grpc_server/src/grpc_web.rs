@@ -0,0 +1,254 @@
+use axum::http::header::HeaderValue;
+
+/// Lets pages from `origins`, and only them, call the gRPC services from browsers. Game tokens are
+/// sent explicitly rather than as cookies, so credentials are not allowed.
+pub fn config(origins: Vec<HeaderValue>) -> tonic_web::Config {
+    tonic_web::config()
+        .allow_origins(origins)
+        .allow_credentials(false)
+        .expose_headers(["grpc-status-details-bin"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth;
+    use crate::daily::{DailySecret, SystemDateSource};
+    use crate::registry::ServiceLimits;
+    use crate::service::SolitaireService;
+    use crate::storage::MemoryStorage;
+    use axum::body::Bytes;
+    use axum::http::header::{ACCEPT, ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE, ORIGIN};
+    use axum::http::{Request, Response, StatusCode};
+    use hyper::Body;
+    use prost::Message;
+    use solitaire_grpc::proto::solitaire_server::SolitaireServer;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+    use tonic::body::BoxBody;
+    use tonic::codegen::InterceptedService;
+    use tower::{Service, ServiceExt};
+
+    /// The Solitaire service as browsers reach it, tonic-web does not name its wrapper.
+    trait WebServer:
+        Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible> + Clone
+    {
+    }
+
+    impl<S> WebServer for S where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible> + Clone
+    {
+    }
+
+    const ORIGIN_ALLOWED: &str = "https://solitaire.example";
+    /// Marks the frame carrying the trailers at the end of a gRPC-Web response body.
+    const TRAILERS_FLAG: u8 = 0x80;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Encoding {
+        Binary,
+        /// Base64, for clients which cannot read binary response bodies as they arrive.
+        Text,
+    }
+
+    impl Encoding {
+        fn content_type(self) -> &'static str {
+            match self {
+                Self::Binary => "application/grpc-web+proto",
+                Self::Text => "application/grpc-web-text+proto",
+            }
+        }
+
+        fn decode(self, data: &[u8]) -> Vec<u8> {
+            match self {
+                Self::Binary => data.to_vec(),
+                Self::Text => base64::decode(data).unwrap(),
+            }
+        }
+    }
+
+    fn web_server(origins: Vec<HeaderValue>) -> impl WebServer {
+        let service = SolitaireService::new(
+            ServiceLimits::default(),
+            Box::new(MemoryStorage::default()),
+            Arc::default(),
+            Box::new(SystemDateSource),
+            DailySecret::new(b"test"),
+        );
+        config(origins).enable(InterceptedService::new(
+            SolitaireServer::from_arc(service),
+            auth::intercept,
+        ))
+    }
+
+    /// A call of `method` as a browser sends it, with its message framed and encoded.
+    fn web_request(
+        method: &str,
+        encoding: Encoding,
+        token: Option<&str>,
+        message: impl Message,
+    ) -> Request<Body> {
+        let mut frame = vec![0];
+        frame.extend((message.encoded_len() as u32).to_be_bytes());
+        message.encode(&mut frame).unwrap();
+        let body = match encoding {
+            Encoding::Binary => frame,
+            Encoding::Text => base64::encode(frame).into_bytes(),
+        };
+        let mut request = Request::post(format!("/solitaire.Solitaire/{method}"))
+            .header(CONTENT_TYPE, encoding.content_type())
+            .header(ACCEPT, encoding.content_type())
+            .header("x-grpc-web", "1");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {token}"));
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    /// Splits a decoded response body into its frames, with their flags.
+    fn frames(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while data.len() >= 5 {
+            let len = u32::from_be_bytes(data[1..5].try_into().unwrap()) as usize;
+            if data.len() < 5 + len {
+                break;
+            }
+            frames.push((data[0], data[5..5 + len].to_vec()));
+            data = &data[5 + len..];
+        }
+        frames
+    }
+
+    async fn call<M: Message + Default>(
+        server: &impl WebServer,
+        method: &str,
+        encoding: Encoding,
+        token: Option<&str>,
+        message: impl Message,
+    ) -> M {
+        let request = web_request(method, encoding, token, message);
+        let response = server.clone().oneshot(request).await.unwrap();
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            encoding.content_type(),
+            "{method}"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let frames = frames(&encoding.decode(&body));
+        assert_eq!(frames.len(), 2, "{method}");
+        assert_eq!(frames[0].0, 0);
+        assert_eq!(frames[1], (TRAILERS_FLAG, b"grpc-status:0\r\n".to_vec()));
+        M::decode(&frames[0].1[..]).unwrap()
+    }
+
+    /// Reads the next message of a streaming response, whose chunks are encoded separately.
+    async fn next_message<M: Message + Default, B>(encoding: Encoding, body: &mut B) -> M
+    where
+        B: http_body::Body<Data = Bytes> + Unpin,
+        B::Error: std::fmt::Debug,
+    {
+        let mut data = Vec::new();
+        loop {
+            let chunk = body.data().await.unwrap().unwrap();
+            data.extend(encoding.decode(&chunk));
+            if let Some((flag, message)) = frames(&data).pop() {
+                assert_eq!(flag, 0);
+                return M::decode(&message[..]).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn games_are_played_from_browsers() {
+        let server = web_server(Vec::new());
+        for encoding in [Encoding::Binary, Encoding::Text] {
+            let created: solitaire_grpc::proto::CreateGameResponse = call(
+                &server,
+                "CreateGame",
+                encoding,
+                None,
+                solitaire_grpc::proto::CreateGameRequest {
+                    require_spectator_token: true,
+                    ..Default::default()
+                },
+            )
+            .await;
+            assert_eq!(created.version, 0);
+            let spectator = created.spectator_token.unwrap();
+
+            let watch = solitaire_grpc::proto::WatchRequest {
+                id: created.id.clone(),
+                from_sequence: None,
+                deltas: false,
+            };
+            let request = web_request("Watch", encoding, Some(&spectator), watch);
+            let response = server.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.headers()[CONTENT_TYPE], encoding.content_type());
+            let mut updates = response.into_body();
+            let first: solitaire_grpc::proto::WatchResponse =
+                next_message(encoding, &mut updates).await;
+            assert_eq!((first.version, first.state), (0, created.state));
+
+            let act = solitaire_grpc::proto::ActRequest {
+                id: created.id.clone(),
+                action: Some(solitaire_backend::Action::Draw.into()),
+                expected_version: None,
+                idempotency_key: String::new(),
+            };
+            let played: solitaire_grpc::proto::ActResponse =
+                call(&server, "Act", encoding, Some(&created.owner_token), act).await;
+            let second: solitaire_grpc::proto::WatchResponse =
+                next_message(encoding, &mut updates).await;
+            assert_eq!((second.version, second.state), (1, played.state));
+
+            // Statuses of calls refused outright are in the headers
+            let watch = solitaire_grpc::proto::WatchRequest {
+                id: created.id,
+                from_sequence: None,
+                deltas: false,
+            };
+            let request = web_request("Watch", encoding, None, watch);
+            let response = server.clone().oneshot(request).await.unwrap();
+            let status = tonic::Status::from_header_map(response.headers()).unwrap();
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_configured_origins_are_allowed() {
+        let server = web_server(vec![HeaderValue::from_static(ORIGIN_ALLOWED)]);
+        let request = |origin| {
+            let mut request = web_request(
+                "ListGames",
+                Encoding::Binary,
+                None,
+                solitaire_grpc::proto::ListGamesRequest::default(),
+            );
+            request
+                .headers_mut()
+                .insert(ORIGIN, HeaderValue::from_static(origin));
+            request
+        };
+        let response = server
+            .clone()
+            .oneshot(request(ORIGIN_ALLOWED))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            ORIGIN_ALLOWED
+        );
+        let response = server
+            .clone()
+            .oneshot(request("https://elsewhere.example"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // No origin is allowed unless configured
+        let server = web_server(Vec::new());
+        let response = server.oneshot(request(ORIGIN_ALLOWED)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,398 @@
+use crate::auth;
+use crate::limits::ClientAddr;
+use crate::metrics::{self, Metrics};
+use crate::service::SolitaireService;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Extension, Path, Query};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_core::Stream;
+use solitaire_grpc::proto::solitaire_server::Solitaire;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+
+const LAST_EVENT_ID: &str = "last-event-id";
+const CORS_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A failed call, answered with the HTTP status closest to its gRPC code and a JSON body.
+pub struct ApiError(tonic::Status);
+
+impl From<tonic::Status> for ApiError {
+    fn from(status: tonic::Status) -> Self {
+        Self(status)
+    }
+}
+
+fn http_status(code: tonic::Code) -> StatusCode {
+    use tonic::Code::*;
+    match code {
+        Ok => StatusCode::OK,
+        InvalidArgument | FailedPrecondition | OutOfRange => StatusCode::BAD_REQUEST,
+        Unauthenticated => StatusCode::UNAUTHORIZED,
+        PermissionDenied => StatusCode::FORBIDDEN,
+        NotFound => StatusCode::NOT_FOUND,
+        AlreadyExists | Aborted => StatusCode::CONFLICT,
+        ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Cancelled => StatusCode::REQUEST_TIMEOUT,
+        Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Unknown | Internal | DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn error_body(status: &tonic::Status) -> serde_json::Value {
+    let mut body = serde_json::json!({
+        "code": format!("{:?}", status.code()),
+        "message": status.message(),
+    });
+    let violations = solitaire_grpc::field_violations(status);
+    if !violations.is_empty() {
+        body["fieldViolations"] = violations
+            .iter()
+            .map(|v| serde_json::json!({ "field": v.field, "description": v.description }))
+            .collect();
+    }
+    body
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (http_status(self.0.code()), Json(error_body(&self.0))).into_response();
+        // Retry-After is in whole seconds, waiting a little longer does no harm
+        if let Some(delay) = solitaire_grpc::retry_delay(&self.0) {
+            let seconds = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(axum::http::header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// The client of a request, known unless the router is called without a server.
+type Client = Option<ConnectInfo<ClientAddr>>;
+
+/// Builds the request the gRPC service expects, with the tokens and the client of the HTTP
+/// request.
+fn grpc_request<T>(
+    headers: &HeaderMap,
+    client: Client,
+    message: T,
+) -> Result<tonic::Request<T>, ApiError> {
+    let mut request = tonic::Request::new(message);
+    *request.metadata_mut() = tonic::metadata::MetadataMap::from_headers(headers.clone());
+    if let Some(ConnectInfo(client)) = client {
+        request.extensions_mut().insert(client);
+    }
+    auth::extract_tokens(&mut request)?;
+    Ok(request)
+}
+
+async fn create_game(
+    Extension(service): Extension<Arc<SolitaireService>>,
+    headers: HeaderMap,
+    client: Client,
+    Json(message): Json<solitaire_grpc::proto::CreateGameRequest>,
+) -> ApiResult<solitaire_grpc::proto::CreateGameResponse> {
+    let response = service
+        .create_game(grpc_request(&headers, client, message)?)
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn list_games(
+    Extension(service): Extension<Arc<SolitaireService>>,
+    headers: HeaderMap,
+    client: Client,
+) -> ApiResult<solitaire_grpc::proto::ListGamesResponse> {
+    let message = solitaire_grpc::proto::ListGamesRequest {};
+    let response = service
+        .list_games(grpc_request(&headers, client, message)?)
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn get_game(
+    Extension(service): Extension<Arc<SolitaireService>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    client: Client,
+) -> ApiResult<solitaire_grpc::proto::GetGameResponse> {
+    let message = solitaire_grpc::proto::GetGameRequest { id };
+    let response = service
+        .get_game(grpc_request(&headers, client, message)?)
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn reveal_deal(
+    Extension(service): Extension<Arc<SolitaireService>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    client: Client,
+) -> ApiResult<solitaire_grpc::proto::RevealDealResponse> {
+    let message = solitaire_grpc::proto::RevealDealRequest { id };
+    let response = service
+        .reveal_deal(grpc_request(&headers, client, message)?)
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn destroy_game(
+    Extension(service): Extension<Arc<SolitaireService>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    client: Client,
+) -> ApiResult<solitaire_grpc::proto::DestroyGameResponse> {
+    let message = solitaire_grpc::proto::DestroyGameRequest { id };
+    let response = service
+        .destroy_game(grpc_request(&headers, client, message)?)
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+/// The body is an `ActRequest`, whose id is taken from the path.
+async fn act(
+    Extension(service): Extension<Arc<SolitaireService>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    client: Client,
+    Json(message): Json<solitaire_grpc::proto::ActRequest>,
+) -> ApiResult<solitaire_grpc::proto::ActResponse> {
+    let message = solitaire_grpc::proto::ActRequest { id, ..message };
+    let response = service
+        .act(grpc_request(&headers, client, message)?)
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+fn watch_event(message: Result<solitaire_grpc::proto::WatchResponse, tonic::Status>) -> Event {
+    match message {
+        Ok(response) => Event::default()
+            .id(response.sequence.to_string())
+            .json_data(&response)
+            .unwrap_or_else(|e| Event::default().event("error").data(e.to_string())),
+        // The stream of a game only fails once the game is closed, with the reason why
+        Err(status) => Event::default()
+            .event("closed")
+            .data(error_body(&status).to_string()),
+    }
+}
+
+/// Mirrors `Watch` as server-sent events, numbered with the sequence of the updates. A client
+/// reconnecting with `Last-Event-ID` resumes after the last update it got.
+async fn watch(
+    Extension(service): Extension<Arc<SolitaireService>>,
+    Path(id): Path<String>,
+    Query(query): Query<solitaire_grpc::proto::WatchRequest>,
+    headers: HeaderMap,
+    client: Client,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let last_event_id = match headers.get(LAST_EVENT_ID) {
+        None => None,
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .ok_or_else(|| tonic::Status::invalid_argument("Invalid Last-Event-ID"))?,
+        ),
+    };
+    let message = solitaire_grpc::proto::WatchRequest {
+        id,
+        from_sequence: last_event_id.map(|s| s + 1).or(query.from_sequence),
+        deltas: query.deltas,
+    };
+    let updates = service
+        .watch(grpc_request(&headers, client, message)?)
+        .await?;
+    let events = updates.into_inner().map(|message| Ok(watch_event(message)));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Metrics of the server, in the Prometheus text format.
+async fn get_metrics(Extension(metrics): Extension<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(axum::http::header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        metrics.encode(),
+    )
+}
+
+/// Lets pages from `origins`, and only them, call the gateway from browsers.
+pub fn cors_layer(origins: Vec<HeaderValue>) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([
+            AUTHORIZATION,
+            CONTENT_TYPE,
+            HeaderName::from_static(LAST_EVENT_ID),
+        ])
+        .max_age(CORS_MAX_AGE)
+}
+
+/// JSON bodies are the protocol messages in the proto3 JSON mapping, with the conversions of
+/// `solitaire_grpc`: fields are named in lowerCamelCase, enums by their names and 64 bit integers
+/// are sent as strings.
+/// Bodies larger than `max_request_size` bytes are refused. The metrics are those of every service,
+/// but only the games of `service` are served: other game services are only reached over gRPC.
+pub fn router(
+    service: Arc<SolitaireService>,
+    metrics: Arc<Metrics>,
+    max_request_size: usize,
+) -> Router<Body> {
+    Router::new()
+        .route("/games", get(list_games).post(create_game))
+        .route("/games/:id", get(get_game).delete(destroy_game))
+        .route("/games/:id/actions", post(act))
+        .route("/games/:id/deal", get(reveal_deal))
+        .route("/games/:id/events", get(watch))
+        .route("/metrics", get(get_metrics))
+        .layer(Extension(service))
+        .layer(Extension(metrics))
+        .layer(RequestBodyLimitLayer::new(max_request_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daily::{DailySecret, SystemDateSource};
+    use crate::registry::ServiceLimits;
+    use crate::storage::MemoryStorage;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn call(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {token}"));
+        }
+        let response = router
+            .clone()
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn games_are_played_over_json() {
+        let router = router(
+            SolitaireService::new(
+                ServiceLimits::default(),
+                Box::new(MemoryStorage::default()),
+                Arc::default(),
+                Box::new(SystemDateSource),
+                DailySecret::new(b"test"),
+            ),
+            Arc::default(),
+            1024,
+        );
+
+        let (status, created) = call(
+            &router,
+            "POST",
+            "/games",
+            None,
+            serde_json::json!({ "config": { "seed": 42, "scoring": "Vegas" } }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let id = created["id"].as_str().unwrap();
+        let token = created["ownerToken"].as_str().unwrap();
+        assert_eq!(created["config"]["drawCount"], 1);
+        assert_eq!(created["config"]["scoring"], "Vegas");
+
+        let draw = serde_json::json!({ "action": { "draw": {} } });
+        let actions = format!("/games/{id}/actions");
+        let (status, error) = call(&router, "POST", &actions, None, draw.clone()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error["code"], "Unauthenticated");
+        let (status, acted) = call(&router, "POST", &actions, Some(token), draw).await;
+        assert_eq!(status, StatusCode::OK);
+        // 64 bit integers are sent as strings
+        assert_eq!(acted["version"], "1");
+        let build = serde_json::json!({
+            "action": { "buildFoundation": { "tableau": { "index": 9 } } },
+            "expectedVersion": "1"
+        });
+        let (status, error) = call(&router, "POST", &actions, Some(token), build).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error["fieldViolations"][0]["field"],
+            "action.build_foundation.tableau.index"
+        );
+
+        let (status, game) = call(
+            &router,
+            "GET",
+            &format!("/games/{id}"),
+            None,
+            serde_json::Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(game["state"], acted["state"]);
+
+        let request = Request::get(format!("/games/{id}/events?fromSequence=1&deltas=true"))
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (status, _) = call(
+            &router,
+            "GET",
+            "/games/not-a-uuid",
+            None,
+            serde_json::Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let large = serde_json::json!({ "player": "x".repeat(2048) });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/games")
+            .header("content-type", "application/json")
+            .body(Body::from(large.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn exhausted_calls_are_retried_after_a_delay() {
+        let status = solitaire_grpc::retry_status(
+            tonic::Code::ResourceExhausted,
+            "Too many calls",
+            std::time::Duration::from_millis(1500),
+        );
+        let response = ApiError(status).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "2");
+    }
+}
@@ -0,0 +1,244 @@
+use axum::extract::connect_info::Connected;
+use bytes::Bytes;
+use hyper::server::conn::AddrStream;
+use hyper::{Body, Request};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tokio_stream::StreamExt;
+use tower::{Layer, Service};
+
+/// Calls a client may make: `burst` at once, then `per_second` on average.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn is_valid(&self) -> bool {
+        self.per_second.is_finite() && self.per_second > 0.0 && self.burst > 0
+    }
+}
+
+/// Calls left to a client, refilled over time up to the burst.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Limits the rate of calls of each client, told apart by their IP address.
+pub struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Counts a call of the client of `request`, failing with the time to wait before calling
+    /// again. Calls made in the server itself come from no client and are not limited.
+    pub fn check<T>(&self, request: &tonic::Request<T>) -> Result<(), tonic::Status> {
+        let client = match client_of(request) {
+            None => return Ok(()),
+            Some(ClientAddr(addr)) => addr.ip(),
+        };
+        self.check_at(client, Instant::now()).map_err(|delay| {
+            solitaire_grpc::retry_status(
+                tonic::Code::ResourceExhausted,
+                format!("Too many calls, retry in {:.1}s", delay.as_secs_f64()),
+                delay,
+            )
+        })
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.limit.burst as f64,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.limit.per_second,
+            ))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64)
+    }
+
+    /// Forgets the clients which could make a full burst of calls again.
+    pub fn prune(&self) {
+        self.prune_at(Instant::now());
+    }
+
+    fn prune_at(&self, now: Instant) {
+        let burst = self.limit.burst as f64;
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| self.refilled(bucket, now) < burst);
+    }
+}
+
+/// Address of the client of a call made through the HTTP gateway, which has no connection of its
+/// own to the gRPC service.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(pub SocketAddr);
+
+impl Connected<&AddrStream> for ClientAddr {
+    fn connect_info(target: &AddrStream) -> Self {
+        Self(target.remote_addr())
+    }
+}
+
+impl Connected<&TlsStream<TcpStream>> for ClientAddr {
+    fn connect_info(target: &TlsStream<TcpStream>) -> Self {
+        // An accepted connection always has a peer, unless it is already gone
+        let addr = target.get_ref().0.peer_addr();
+        Self(addr.unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0))))
+    }
+}
+
+/// The client of a call, made through the gateway or directly.
+pub fn client_of<T>(request: &tonic::Request<T>) -> Option<ClientAddr> {
+    match request.extensions().get::<ClientAddr>() {
+        Some(client) => Some(*client),
+        None => request.remote_addr().map(ClientAddr),
+    }
+}
+
+/// Fails the gRPC calls whose request is larger than `max_size` bytes with `RESOURCE_EXHAUSTED`.
+/// The size is counted as the request is read, streaming calls are limited in total.
+#[derive(Clone)]
+pub struct RequestSizeLimitLayer {
+    max_size: usize,
+}
+
+impl RequestSizeLimitLayer {
+    pub fn new(max_size: usize) -> Self {
+        Self { max_size }
+    }
+}
+
+impl<S> Layer<S> for RequestSizeLimitLayer {
+    type Service = RequestSizeLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestSizeLimit {
+            inner,
+            max_size: self.max_size,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestSizeLimit<S> {
+    inner: S,
+    max_size: usize,
+}
+
+impl<S> Service<Request<Body>> for RequestSizeLimit<S>
+where
+    S: Service<Request<Body>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let max_size = self.max_size;
+        self.inner
+            .call(request.map(|body| limit_body(body, max_size)))
+    }
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The body fails with the status of the call once it goes over the limit, the service reading
+/// it answers with that status.
+fn limit_body(body: Body, max_size: usize) -> Body {
+    let mut size = 0;
+    Body::wrap_stream(body.map(move |chunk| -> Result<Bytes, BoxError> {
+        let chunk = chunk?;
+        size += chunk.len();
+        if size > max_size {
+            return Err(Box::new(tonic::Status::resource_exhausted(format!(
+                "Request larger than {max_size} bytes"
+            ))));
+        }
+        Ok(chunk)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_are_limited_separately() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 2.0,
+            burst: 2,
+        });
+        let start = Instant::now();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert!(limiter.check_at(client, start).is_ok());
+        assert!(limiter.check_at(client, start).is_ok());
+        assert_eq!(
+            limiter.check_at(client, start),
+            Err(Duration::from_millis(500))
+        );
+        assert!(limiter.check_at(other, start).is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at(client, later).is_ok());
+        assert!(limiter.check_at(client, later).is_err());
+
+        // Once refilled, the clients are no longer kept
+        limiter.prune_at(later);
+        assert_eq!(
+            limiter.buckets.lock().unwrap().keys().collect::<Vec<_>>(),
+            [&client]
+        );
+        limiter.prune_at(later + Duration::from_secs(1));
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn large_requests_are_refused() {
+        let body = limit_body(Body::from(vec![0; 16]), 16);
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap().len(), 16);
+
+        let body = limit_body(Body::from(vec![0; 17]), 16);
+        let error = hyper::body::to_bytes(body).await.unwrap_err();
+        let source = std::error::Error::source(&error).unwrap();
+        let status = source.downcast_ref::<tonic::Status>().unwrap();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    }
+}
@@ -1,181 +1,210 @@
-use boards::random_engine::DefaultRandomEngine;
-use solitaire_backend::{ActionResult, Game, MemoryGame};
-use std::collections::HashMap;
-use std::str::FromStr;
-use tokio;
-use tokio::sync::{mpsc, Mutex};
-use tokio_stream::wrappers::ReceiverStream;
-use uuid::Uuid;
-
-type WatchMessage = Result<solitaire_grpc::proto::WatchResponse, tonic::Status>;
-
-struct ActiveGame {
-    state: MemoryGame,
-    streams: Vec<mpsc::Sender<WatchMessage>>,
-}
-
-impl Default for ActiveGame {
-    fn default() -> Self {
-        Self {
-            state: MemoryGame::new(&mut DefaultRandomEngine::new()),
-            streams: Vec::default(),
-        }
-    }
-}
-
-impl ActiveGame {
-    async fn send_watch_message(&mut self, ref msg: WatchMessage) {
-        let mut i = 0usize;
-        while i < self.streams.len() {
-            if self.streams[i]
-                .send(match msg {
-                    Err(status) => Err(tonic::Status::new(status.code(), status.message())),
-                    Ok(msg) => Ok(msg.clone()),
-                })
-                .await
-                .is_ok()
-            {
-                i += 1;
-            } else {
-                self.streams.swap_remove(i);
+// Every call of the services fails with a `tonic::Status`, which is large, and so do the
+// functions checking their requests
+#![allow(clippy::result_large_err)]
+
+use axum::http::HeaderValue;
+use std::env;
+use std::future::Future;
+use std::io::IsTerminal;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+mod auth;
+mod config;
+mod daily;
+mod grpc_web;
+mod http;
+mod limits;
+mod metrics;
+mod race;
+mod registry;
+mod service;
+mod solitaire;
+mod stats;
+mod storage;
+mod tls;
+use config::Config;
+use daily::DailySecret;
+use registry::{run_reaper, GameService};
+use service::SolitaireService;
+use solitaire::SolitaireRules;
+use solitaire_grpc::proto::solitaire_server::SolitaireServer;
+use storage::{FileStorage, GameStorage, MemoryStorage};
+use tonic::codegen::InterceptedService;
+use tonic_health::ServingStatus;
+
+/// Calls still running this long after a shutdown are cut off.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Resolves on ctrl-c, or on SIGTERM on platforms which have it.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending().await
             }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate => (),
     }
 }
 
-#[derive(Default)]
-struct SolitareServiceState {
-    games: HashMap<Uuid, ActiveGame>,
-}
-
-fn new_not_found_status(id: &Uuid) -> tonic::Status {
-    tonic::Status::not_found(format!("Game not found: {id}"))
-}
-
-impl SolitareServiceState {
-    fn get_mut_game(&mut self, id: &Uuid) -> Result<&mut ActiveGame, tonic::Status> {
-        match self.games.get_mut(&id) {
-            None => Err(new_not_found_status(&id)),
-            Some(game) => Ok(game),
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::from_args(env::args().skip(1))?;
+    // Each call is logged once over, with its fields and duration
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(std::io::stdout().is_terminal())
+        .init();
+
+    // Every game service is counted in the same metrics, and stored in the same directory
+    let metrics = Arc::new(metrics::Metrics::default());
+    let storage: Box<dyn GameStorage<SolitaireRules>> = match &config.storage {
+        None => Box::new(MemoryStorage::default()),
+        Some(dir) => Box::new(FileStorage::new(dir.clone()).await?),
+    };
+
+    let daily_secret = match &config.daily_secret {
+        Some(secret) => DailySecret::new(secret.as_bytes()),
+        None => {
+            tracing::warn!(
+                "No daily secret given, daily deals will change when the server restarts"
+            );
+            DailySecret::random()
+        }
+    };
+    let service = SolitaireService::new(
+        config.limits(),
+        storage,
+        metrics.clone(),
+        Box::new(daily::SystemDateSource),
+        daily_secret,
+    );
+    // Every game service hosted by the server over gRPC, the JSON gateway only serves solitaire
+    let services: Vec<Arc<dyn GameService>> = vec![service.clone()];
+    for hosted in services.iter() {
+        let restored = hosted.restore().await?;
+        if restored > 0 {
+            tracing::info!("Restored {restored} games of {}", hosted.name());
         }
     }
-}
-
-#[derive(Default)]
-struct SolitaireService {
-    state: Mutex<SolitareServiceState>,
-}
 
-fn try_parse_id(id: &str) -> Result<Uuid, tonic::Status> {
-    Uuid::from_str(id).map_err(|err| tonic::Status::invalid_argument(format!("Invalid id: {err}")))
-}
+    tokio::spawn(run_reaper(services.clone()));
+
+    let scheme = if config.tls.is_some() {
+        "TLS"
+    } else {
+        "plaintext"
+    };
+    tracing::info!("Solitaire server listening on {} ({scheme})", config.listen);
+    tracing::info!(
+        "HTTP gateway listening on {} ({scheme})",
+        config.http_listen
+    );
+
+    // Browsers of other origins are refused unless these are configured
+    let cors_origins: Vec<HeaderValue> = config
+        .cors_origins
+        .iter()
+        .map(|origin| origin.parse())
+        .collect::<Result<_, _>>()?;
+    let router = http::router(service.clone(), metrics.clone(), config.max_request_size)
+        .layer(http::cors_layer(cors_origins.clone()));
+    let router = router.into_make_service_with_connect_info::<limits::ClientAddr>();
+    // Both servers stop accepting calls once the games are closed
+    let (stop, stopped) = watch::channel(());
+    let stopped = move || {
+        let mut stopped = stopped.clone();
+        async move {
+            let _ = stopped.changed().await;
+        }
+    };
+    let mut http: Pin<Box<dyn Future<Output = Result<(), hyper::Error>> + Send>> = match &config.tls
+    {
+        None => Box::pin(
+            axum::Server::bind(&config.http_listen)
+                .serve(router)
+                .with_graceful_shutdown(stopped()),
+        ),
+        Some(tls) => {
+            let listener = TcpListener::bind(config.http_listen).await?;
+            let incoming = tls::incoming(listener, tls::http_acceptor(tls)?);
+            Box::pin(
+                axum::Server::builder(hyper::server::accept::from_stream(incoming))
+                    .serve(router)
+                    .with_graceful_shutdown(stopped()),
+            )
+        }
+    };
 
-#[tonic::async_trait]
-impl solitaire_grpc::proto::solitaire_server::Solitaire for SolitaireService {
-    async fn create_game(
-        &self,
-        _request: tonic::Request<solitaire_grpc::proto::CreateGameRequest>,
-    ) -> Result<tonic::Response<solitaire_grpc::proto::CreateGameResponse>, tonic::Status> {
-        let id = Uuid::new_v4();
-        let mut state = self.state.lock().await;
-        state.games.insert(id, ActiveGame::default());
-        let ref game_state = state.games.get(&id).unwrap();
-        Ok(tonic::Response::new(
-            solitaire_grpc::proto::CreateGameResponse {
-                id: id.to_string(),
-                state: Some((&game_state.state).into()),
-            },
-        ))
+    let (mut health, health_server) = tonic_health::server::health_reporter();
+    for hosted in services.iter() {
+        health
+            .set_service_status(hosted.name(), ServingStatus::Serving)
+            .await;
     }
-
-    async fn destroy_game(
-        &self,
-        request: tonic::Request<solitaire_grpc::proto::DestroyGameRequest>,
-    ) -> Result<tonic::Response<solitaire_grpc::proto::DestroyGameResponse>, tonic::Status> {
-        let id = try_parse_id(&request.get_ref().id)?;
-        let mut state = self.state.lock().await;
-        match state.games.remove(&id) {
-            None => Err(new_not_found_status(&id)),
-            Some(mut game) => {
-                std::mem::drop(state);
-                game.send_watch_message(Err(tonic::Status::ok("Game destroyed")))
-                    .await;
-                Ok(tonic::Response::new(
-                    solitaire_grpc::proto::DestroyGameResponse {},
-                ))
-            }
-        }
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(solitaire_grpc::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    // Browsers reach the gRPC services through gRPC-Web, over HTTP/1.1
+    let grpc_web = grpc_web::config(cors_origins);
+    let mut grpc = tonic::transport::Server::builder();
+    if let Some(tls) = &config.tls {
+        grpc = grpc.tls_config(tls::grpc_config(tls)?)?;
     }
-
-    async fn act(
-        &self,
-        request: tonic::Request<solitaire_grpc::proto::ActRequest>,
-    ) -> Result<tonic::Response<solitaire_grpc::proto::ActResponse>, tonic::Status> {
-        // let request = request;
-        let id = try_parse_id(&request.get_ref().id)?;
-        match request.into_inner().action {
-            None => Err(tonic::Status::invalid_argument("Missing field 'action'")),
-            Some(proto_action) => {
-                let action = (&proto_action).try_into()?;
-                let mut state = self.state.lock().await;
-                let ref mut game = state.get_mut_game(&id)?;
-                let result = game.state.act(action).await;
-                if let ActionResult::Failed(s) = result {
-                    Err(tonic::Status::failed_precondition(format!(
-                        "Invalid move: {s}"
-                    )))
-                } else {
-                    game.send_watch_message(Ok(solitaire_grpc::proto::WatchResponse {
-                        action: Some(proto_action),
-                        state: Some((&game.state).into()),
-                    }))
-                    .await;
-                    let new_state = (&game.state).into();
-                    std::mem::drop(state);
-                    Ok(tonic::Response::new(solitaire_grpc::proto::ActResponse {
-                        victory: match result {
-                            ActionResult::Victory => true,
-                            _ => false,
-                        },
-                        state: Some(new_state),
-                    }))
-                }
-            }
-        }
+    let grpc = grpc
+        .accept_http1(true)
+        .layer(limits::RequestSizeLimitLayer::new(config.max_request_size))
+        .layer(metrics::RpcMetricsLayer::new(metrics.rpc_duration.clone()))
+        .add_service(grpc_web.enable(health_server))
+        .add_service(grpc_web.enable(reflection))
+        .add_service(grpc_web.enable(InterceptedService::new(
+            SolitaireServer::from_arc(service.clone()),
+            auth::intercept,
+        )))
+        .serve_with_shutdown(config.listen, stopped());
+    tokio::pin!(grpc);
+    tokio::select! {
+        result = &mut grpc => result?,
+        result = &mut http => result?,
+        _ = shutdown_signal() => (),
     }
 
-    type WatchStream = ReceiverStream<WatchMessage>;
-
-    async fn watch(
-        &self,
-        request: tonic::Request<solitaire_grpc::proto::WatchRequest>,
-    ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
-        let id = try_parse_id(&request.get_ref().id)?;
-        let mut state = self.state.lock().await;
-        let ref mut game = state.get_mut_game(&id)?;
-        let (tx, rx) = mpsc::channel(128);
-        tx.send(Ok(solitaire_grpc::proto::WatchResponse {
-            action: None,
-            state: Some((&game.state).into()),
-        }))
-        .await
-        .unwrap();
-        game.streams.push(tx);
-        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    tracing::info!("Shutting down");
+    // Health checks fail from now on, for the server as a whole, named "", and each service
+    for name in std::iter::once("").chain(services.iter().map(|hosted| hosted.name())) {
+        health
+            .set_service_status(name, ServingStatus::NotServing)
+            .await;
+    }
+    for hosted in services.iter() {
+        hosted.close(config.snapshot.as_deref()).await;
+    }
+    let _ = stop.send(());
+    match tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, async { tokio::join!(grpc, http) }).await {
+        Ok((grpc, http)) => {
+            grpc?;
+            http?;
+        }
+        Err(_) => tracing::warn!("Calls still running after the grace period were cut off"),
     }
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::1]:50051".parse().unwrap();
-    let service = SolitaireService::default();
-
-    println!("Solitaire server listening on {}", addr);
-
-    tonic::transport::Server::builder()
-        .add_service(solitaire_grpc::proto::solitaire_server::SolitaireServer::new(service))
-        .serve(addr)
-        .await?;
 
     Ok(())
 }
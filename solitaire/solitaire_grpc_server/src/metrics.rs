@@ -0,0 +1,182 @@
+use axum::http::{HeaderValue, Request, Response};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower::{Layer, Service};
+
+/// Content type of the Prometheus text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// What the server counts, exported in the Prometheus text format. Rates, such as actions per
+/// second, are left to the queries.
+pub struct Metrics {
+    registry: Registry,
+    pub active_games: IntGauge,
+    pub watchers: IntGauge,
+    pub actions: IntCounter,
+    /// Moves refused by the rules or played on an outdated version, by reason.
+    pub failed_moves: IntCounterVec,
+    pub victories: IntCounter,
+    /// Time until the response of each gRPC method starts, by method.
+    pub rpc_duration: HistogramVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let metrics = Self {
+            registry: Registry::new_custom(Some("solitaire".to_owned()), None).unwrap(),
+            active_games: IntGauge::new("active_games", "Games in memory").unwrap(),
+            watchers: IntGauge::new("watchers", "Open Watch streams").unwrap(),
+            actions: IntCounter::new("actions_total", "Moves played").unwrap(),
+            failed_moves: IntCounterVec::new(
+                Opts::new("failed_moves_total", "Moves refused, by reason"),
+                &["reason"],
+            )
+            .unwrap(),
+            victories: IntCounter::new("victories_total", "Games won").unwrap(),
+            rpc_duration: HistogramVec::new(
+                HistogramOpts::new("rpc_duration_seconds", "Latency of gRPC calls"),
+                &["method"],
+            )
+            .unwrap(),
+        };
+        // Names are fixed, so registering can only fail on a programming error
+        let collectors: [Box<dyn prometheus::core::Collector>; 6] = [
+            Box::new(metrics.active_games.clone()),
+            Box::new(metrics.watchers.clone()),
+            Box::new(metrics.actions.clone()),
+            Box::new(metrics.failed_moves.clone()),
+            Box::new(metrics.victories.clone()),
+            Box::new(metrics.rpc_duration.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+}
+
+impl Metrics {
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        // Writing to a vector does not fail
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    /// Counts a refused move under a label made of its reason, such as `no_redeals_left`.
+    pub fn failed_move(&self, reason: &str) {
+        let reason = reason.to_lowercase().replace(' ', "_");
+        self.failed_moves.with_label_values(&[&reason]).inc();
+    }
+}
+
+/// Counts something for as long as it is alive.
+pub struct GaugeGuard(IntGauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: &IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge.clone())
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Measures the calls to the services behind it into a histogram labelled with their path, which
+/// is the gRPC method. The response of a streaming call starts before its first message. Calls to
+/// unknown methods are left out, as their paths are made up by clients.
+#[derive(Clone)]
+pub struct RpcMetricsLayer {
+    duration: HistogramVec,
+}
+
+impl RpcMetricsLayer {
+    pub fn new(duration: HistogramVec) -> Self {
+        Self { duration }
+    }
+}
+
+impl<S> Layer<S> for RpcMetricsLayer {
+    type Service = RpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcMetrics {
+            inner,
+            duration: self.duration.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RpcMetrics<S> {
+    inner: S,
+    duration: HistogramVec,
+}
+
+const GRPC_STATUS: &str = "grpc-status";
+const UNIMPLEMENTED: HeaderValue = HeaderValue::from_static("12");
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+impl<S, Q, B> Service<Request<Q>> for RpcMetrics<S>
+where
+    S: Service<Request<Q>, Response = Response<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Q>) -> Self::Future {
+        let start = Instant::now();
+        let method = request.uri().path().to_owned();
+        let duration = self.duration.clone();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            let unknown = matches!(&response, Ok(response)
+                if response.headers().get(GRPC_STATUS) == Some(&UNIMPLEMENTED));
+            if !unknown {
+                duration
+                    .with_label_values(&[&method])
+                    .observe(start.elapsed().as_secs_f64());
+            }
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_encoded() {
+        let metrics = Metrics::default();
+        metrics.failed_move("No redeals left");
+        {
+            let _watcher = GaugeGuard::new(&metrics.watchers);
+            assert_eq!(metrics.watchers.get(), 1);
+        }
+        assert_eq!(metrics.watchers.get(), 0);
+        let text = metrics.encode();
+        assert!(text.contains("solitaire_failed_moves_total{reason=\"no_redeals_left\"} 1"));
+        assert!(text.contains("solitaire_active_games 0"));
+    }
+}
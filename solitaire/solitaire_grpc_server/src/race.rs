@@ -0,0 +1,162 @@
+use boards::cards::FrenchDeck;
+use solitaire_backend::GameOptions;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+
+const MAX_RACERS: usize = 8;
+const RACE_CHANNEL_SIZE: usize = 64;
+
+type RaceMessage = Result<solitaire_grpc::proto::WatchMatchResponse, tonic::Status>;
+
+struct RaceState {
+    racers: Vec<solitaire_grpc::proto::RacerProgress>,
+    winner: Option<String>,
+}
+
+/// Players racing on the same deal, each on their own game. Every change is broadcast as a full
+/// snapshot of the race, so a watcher that falls behind only needs the latest one.
+pub struct Race {
+    pub deck: FrenchDeck,
+    pub options: GameOptions,
+    state: Mutex<RaceState>,
+    events: broadcast::Sender<solitaire_grpc::proto::WatchMatchResponse>,
+}
+
+impl Race {
+    pub fn new(deck: FrenchDeck, options: GameOptions) -> Self {
+        Self {
+            deck,
+            options,
+            state: Mutex::new(RaceState {
+                racers: Vec::new(),
+                winner: None,
+            }),
+            events: broadcast::channel(RACE_CHANNEL_SIZE).0,
+        }
+    }
+
+    fn snapshot_of(state: &RaceState) -> solitaire_grpc::proto::WatchMatchResponse {
+        solitaire_grpc::proto::WatchMatchResponse {
+            racers: state.racers.clone(),
+            winner: state.winner.clone(),
+        }
+    }
+
+    fn publish(&self, state: &RaceState) {
+        // Nobody watching is fine
+        let _ = self.events.send(Self::snapshot_of(state));
+    }
+
+    /// Reserves a place for `player`, as long as nobody won yet.
+    pub fn join(&self, player: &str) -> Result<(), tonic::Status> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(winner) = &state.winner {
+            Err(tonic::Status::failed_precondition(format!(
+                "The match was already won by {winner}"
+            )))
+        } else if state.racers.iter().any(|r| r.player == player) {
+            Err(tonic::Status::already_exists(format!(
+                "{player} already joined the match"
+            )))
+        } else if state.racers.len() >= MAX_RACERS {
+            Err(tonic::Status::resource_exhausted(format!(
+                "Matches are limited to {MAX_RACERS} players"
+            )))
+        } else {
+            state.racers.push(solitaire_grpc::proto::RacerProgress {
+                player: player.to_owned(),
+                foundation_cards: 0,
+                moves: 0,
+            });
+            self.publish(&state);
+            Ok(())
+        }
+    }
+
+    /// Gives up a place reserved by [`Race::join`] when the game of the player could not be made.
+    pub fn leave(&self, player: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.racers.retain(|r| r.player != player);
+        self.publish(&state);
+    }
+
+    /// Updates the progress of `player`, the first one to win ends the race.
+    pub fn report(&self, player: &str, foundation_cards: u32, moves: u32, victory: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.winner.is_some() {
+            return;
+        }
+        if let Some(racer) = state.racers.iter_mut().find(|r| r.player == player) {
+            racer.foundation_cards = foundation_cards;
+            racer.moves = moves;
+        }
+        if victory {
+            state.winner = Some(player.to_owned());
+        }
+        self.publish(&state);
+    }
+
+    pub fn snapshot(&self) -> solitaire_grpc::proto::WatchMatchResponse {
+        Self::snapshot_of(&self.state.lock().unwrap())
+    }
+}
+
+/// Streams the race from its current state until somebody wins, the race is dropped or the
+/// watcher goes away.
+pub fn spawn_race_watcher(race: &Arc<Race>, tx: mpsc::Sender<RaceMessage>) {
+    let (snapshot, mut events) = {
+        // Subscribing under the lock guarantees no change is missed after the snapshot
+        let state = race.state.lock().unwrap();
+        (Race::snapshot_of(&state), race.events.subscribe())
+    };
+    // The race is dropped along with its last game, which closes the events
+    let race = Arc::downgrade(race);
+    tokio::spawn(async move {
+        let mut next = Some(snapshot);
+        while let Some(response) = next.take() {
+            let over = response.winner.is_some();
+            if tx.send(Ok(response)).await.is_err() || over {
+                return;
+            }
+            next = tokio::select! {
+                _ = tx.closed() => return,
+                event = events.recv() => match event {
+                    Ok(response) => Some(response),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        race.upgrade().map(|race| race.snapshot())
+                    }
+                    Err(broadcast::error::RecvError::Closed) => None,
+                },
+            };
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boards::cards::french::standard_52_deck;
+
+    #[test]
+    fn first_victory_wins() {
+        let race = Race::new(standard_52_deck(), GameOptions::default());
+        race.join("alice").unwrap();
+        race.join("bob").unwrap();
+        assert_eq!(
+            race.join("alice").unwrap_err().code(),
+            tonic::Code::AlreadyExists
+        );
+
+        race.report("alice", 10, 30, false);
+        race.report("bob", 52, 120, true);
+        race.report("alice", 52, 140, true);
+        let snapshot = race.snapshot();
+        assert_eq!(snapshot.winner.as_deref(), Some("bob"));
+        assert_eq!(snapshot.racers[0].foundation_cards, 10);
+        assert_eq!(snapshot.racers[1].moves, 120);
+        assert_eq!(
+            race.join("carol").unwrap_err().code(),
+            tonic::Code::FailedPrecondition
+        );
+    }
+}
@@ -0,0 +1,1363 @@
+use crate::auth::{self, GameToken, GameTokens, IssuedTokens, TokenHash};
+use crate::limits::{self, ClientAddr, RateLimit, RateLimiter};
+use crate::metrics::{GaugeGuard, Metrics};
+use crate::storage::{GameRecord, GameStorage};
+use futures_core::Stream;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock, RwLockReadGuard};
+use tokio_stream::StreamExt;
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub const DEFAULT_WATCH_CHANNEL_SIZE: usize = 128;
+pub const HISTORY_SIZE: usize = 256;
+/// Watchers asking for deltas are sent the full state at least this often.
+pub const STATE_INTERVAL: u32 = 64;
+/// Keys of the last moves played on a game, sent again by clients unsure they were played.
+const KEPT_IDEMPOTENCY_KEYS: usize = 64;
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 64;
+/// Watchers refused by a full game are asked to wait this long, as nobody knows when one leaves.
+const WATCHER_RETRY_DELAY: Duration = Duration::from_secs(5);
+const REAPER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How a move played out.
+pub enum Outcome {
+    Played,
+    Won,
+    Refused(String),
+}
+
+/// What a game hosted by the server provides: its rules, and how its games and moves are
+/// exchanged with clients. The registry does the rest, the same way for every game.
+#[tonic::async_trait]
+pub trait GameRules: Sized + Send + Sync + 'static {
+    /// How a game is dealt, which is stored along with its moves to rebuild it.
+    type Setup: Clone + Send + Sync;
+    type Game: Clone + Send + Sync;
+    type Action: Clone + Display + Send + Sync;
+    /// What a game only keeps in memory, such as the match it is played in.
+    type Attachment: Default + Send + Sync;
+    /// Outcome of a finished game, recorded for its player.
+    type Result: Clone + Send + Sync;
+    type ProtoAction: Clone + Send + Sync;
+    /// The state of a game as players see it.
+    type State: Clone + Send + Sync;
+    type Delta: Clone + Send + Sync;
+    type WatchResponse: Send + 'static;
+    /// Messages of `Play` sessions, from the client and back to it.
+    type PlayRequest: Send + 'static;
+    type PlayResponse: Send + 'static;
+
+    fn deal(setup: &Self::Setup) -> Self::Game;
+    async fn act(game: &mut Self::Game, action: Self::Action) -> Outcome;
+    /// Fails with the status the move is refused with, naming the fields at fault.
+    fn action(action: &Self::ProtoAction) -> Result<Self::Action, tonic::Status>;
+    fn view(game: &Self::Game) -> Self::State;
+    fn delta(from: &Self::State, to: &Self::State) -> Self::Delta;
+    fn checksum(state: &Self::State) -> u64;
+    fn watch_response(update: Update<Self>) -> Self::WatchResponse;
+    fn session_request(request: Self::PlayRequest) -> SessionRequest<Self>;
+    fn session_event(event: SessionEvent<Self>) -> Self::PlayResponse;
+    /// Result to record for the player of the game, if it has one.
+    fn result(game: &ActiveGame<Self>) -> Option<Self::Result>;
+    /// Called with the game after each of its moves.
+    fn played(_game: &ActiveGame<Self>) {}
+}
+
+/// A move as sent to watchers, with both the state it led to and the changes from the previous
+/// one. Watchers are only sent one of them.
+pub struct Update<R: GameRules> {
+    pub action: Option<R::ProtoAction>,
+    pub state: Option<R::State>,
+    pub delta: Option<R::Delta>,
+    /// Checksum of the state the update leads to, even when it is left out.
+    pub checksum: u64,
+    pub version: u64,
+    pub sequence: u64,
+}
+
+impl<R: GameRules> Clone for Update<R> {
+    fn clone(&self) -> Self {
+        Self {
+            action: self.action.clone(),
+            state: self.state.clone(),
+            delta: self.delta.clone(),
+            checksum: self.checksum,
+            version: self.version,
+            sequence: self.sequence,
+        }
+    }
+}
+
+/// A move sent by a client, to the game of id `id`.
+pub struct Move<R: GameRules> {
+    pub id: String,
+    pub action: Option<R::ProtoAction>,
+    pub expected_version: Option<u64>,
+    pub idempotency_key: String,
+}
+
+/// A new watcher of the game of id `id`, sent its updates from `from_sequence` or from the
+/// current state, as deltas when it asks for them.
+pub struct Watch {
+    pub id: String,
+    pub from_sequence: Option<u64>,
+    pub deltas: bool,
+}
+
+/// What a message of a `Play` session asks for.
+pub enum SessionRequest<R: GameRules> {
+    /// Opens the session on a game, the first message of every session and only that one.
+    Open(Watch),
+    /// A move on the game of the session, its result is sent back with `request_id`.
+    Move {
+        request_id: u64,
+        action: Option<R::ProtoAction>,
+        expected_version: Option<u64>,
+        idempotency_key: String,
+    },
+    /// Asks for the current state, sent after the updates already on their way.
+    Snapshot,
+    /// A message asking for nothing.
+    Empty,
+}
+
+/// What a `Play` session sends: the updates of its game, and the results of its moves.
+pub enum SessionEvent<R: GameRules> {
+    Update(R::WatchResponse),
+    Result {
+        request_id: u64,
+        outcome: Result<Played<R>, tonic::Status>,
+    },
+}
+
+/// What a move led to, also answered to clients sending it again.
+pub struct Played<R: GameRules> {
+    pub victory: bool,
+    pub state: R::State,
+    pub version: u64,
+}
+
+impl<R: GameRules> Clone for Played<R> {
+    fn clone(&self) -> Self {
+        Self {
+            victory: self.victory,
+            state: self.state.clone(),
+            version: self.version,
+        }
+    }
+}
+
+/// A game just added, as told to its creator.
+pub struct Created<R: GameRules> {
+    pub id: Uuid,
+    pub state: R::State,
+    pub version: u64,
+    pub tokens: IssuedTokens,
+}
+
+/// Why a game was closed, its watchers are told with the status ending their stream.
+#[derive(Clone, Copy)]
+enum CloseReason {
+    Destroyed,
+    Expired,
+    ShuttingDown,
+}
+
+impl CloseReason {
+    fn status(self) -> tonic::Status {
+        match self {
+            CloseReason::Destroyed => tonic::Status::not_found("Game destroyed"),
+            CloseReason::Expired => tonic::Status::not_found("Game expired"),
+            // The game is still stored, a client may come back once the server is restarted
+            CloseReason::ShuttingDown => tonic::Status::unavailable("Server shutting down"),
+        }
+    }
+}
+
+/// What is broadcast to the watchers of a game.
+enum GameEvent<R: GameRules> {
+    Update(Box<Update<R>>),
+    Closed(CloseReason),
+}
+
+impl<R: GameRules> Clone for GameEvent<R> {
+    fn clone(&self) -> Self {
+        match self {
+            GameEvent::Update(update) => GameEvent::Update(update.clone()),
+            GameEvent::Closed(reason) => GameEvent::Closed(*reason),
+        }
+    }
+}
+
+pub struct ActiveGame<R: GameRules> {
+    pub state: R::Game,
+    /// How the game was dealt and the moves played since, to store it again.
+    pub setup: R::Setup,
+    pub actions: Vec<R::Action>,
+    pub tokens: GameTokens,
+    pub player: Option<String>,
+    pub attachment: R::Attachment,
+    pub created_at: SystemTime,
+    pub last_activity: SystemTime,
+    pub moves: u32,
+    pub victory: bool,
+    pub version: u64,
+    events: broadcast::Sender<GameEvent<R>>,
+    /// Watchers of the game other than its `Play` sessions, which `max_watchers` bounds.
+    spectators: Arc<AtomicUsize>,
+    sequence: u64,
+    history: VecDeque<Update<R>>,
+    /// Outcomes of the last moves sent with an idempotency key.
+    played_keys: VecDeque<(String, Played<R>)>,
+}
+
+pub type GameHandle<R> = Arc<Mutex<ActiveGame<R>>>;
+
+/// What is left of a game which ended without being won.
+pub struct EndedGame<R: GameRules> {
+    pub setup: R::Setup,
+    pub tokens: GameTokens,
+    ended_at: SystemTime,
+}
+
+impl<R: GameRules> Clone for EndedGame<R> {
+    fn clone(&self) -> Self {
+        Self {
+            setup: self.setup.clone(),
+            tokens: self.tokens.clone(),
+            ended_at: self.ended_at,
+        }
+    }
+}
+
+impl<R: GameRules> ActiveGame<R> {
+    /// Deals the game of a record, without playing its moves.
+    fn new(record: &GameRecord<R>, watch_channel_size: usize) -> Self {
+        Self {
+            state: R::deal(&record.setup),
+            setup: record.setup.clone(),
+            actions: Vec::new(),
+            tokens: record.tokens.clone(),
+            player: record.player.clone(),
+            attachment: R::Attachment::default(),
+            created_at: record.created_at,
+            last_activity: SystemTime::now(),
+            moves: 0,
+            victory: false,
+            version: 0,
+            events: broadcast::channel(watch_channel_size).0,
+            spectators: Arc::default(),
+            sequence: 0,
+            history: VecDeque::new(),
+            played_keys: VecDeque::new(),
+        }
+    }
+
+    /// Replays a stored game, failing if one of its moves is no longer valid.
+    async fn restore(record: GameRecord<R>, watch_channel_size: usize) -> Result<Self, String> {
+        let mut game = Self::new(&record, watch_channel_size);
+        for action in record.actions {
+            match R::act(&mut game.state, action.clone()).await {
+                Outcome::Refused(s) => return Err(format!("{action}: {s}")),
+                outcome => game.victory |= matches!(outcome, Outcome::Won),
+            }
+            game.actions.push(action);
+            game.moves += 1;
+            game.version += 1;
+        }
+        // The history is not stored, watchers can only resume from after the restart
+        game.sequence = game.version;
+        Ok(game)
+    }
+
+    fn is_idle(&self, timeout: Duration) -> bool {
+        self.last_activity
+            .elapsed()
+            .map(|idle| idle >= timeout)
+            .unwrap_or(false)
+    }
+
+    fn record(&self) -> GameRecord<R> {
+        GameRecord {
+            setup: self.setup.clone(),
+            tokens: self.tokens.clone(),
+            player: self.player.clone(),
+            created_at: self.created_at,
+            actions: self.actions.clone(),
+        }
+    }
+
+    /// The state as players see it.
+    pub fn view(&self) -> R::State {
+        R::view(&self.state)
+    }
+
+    /// The current state, which the updates following it apply to.
+    pub fn snapshot(&self) -> Update<R> {
+        let state = self.view();
+        Update {
+            action: None,
+            checksum: R::checksum(&state),
+            state: Some(state),
+            delta: None,
+            version: self.version,
+            sequence: self.sequence,
+        }
+    }
+
+    /// Numbers a move, keeps it in the history and sends it to the watchers, with both the state
+    /// it led to and the changes from `previous`.
+    fn publish_update(&mut self, action: R::ProtoAction, previous: &R::State, state: R::State) {
+        self.sequence += 1;
+        let update = Update {
+            action: Some(action),
+            delta: Some(R::delta(previous, &state)),
+            checksum: R::checksum(&state),
+            state: Some(state),
+            version: self.version,
+            sequence: self.sequence,
+        };
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(update.clone());
+        self.publish(GameEvent::Update(Box::new(update)));
+    }
+
+    fn played_response(&self, key: &str) -> Option<Played<R>> {
+        self.played_keys
+            .iter()
+            .find(|(played, _)| played == key)
+            .map(|(_, played)| played.clone())
+    }
+
+    fn remember_played(&mut self, key: String, played: Played<R>) {
+        if self.played_keys.len() == KEPT_IDEMPOTENCY_KEYS {
+            self.played_keys.pop_front();
+        }
+        self.played_keys.push_back((key, played));
+    }
+
+    fn events_since(&self, from_sequence: u64) -> Result<Vec<Update<R>>, tonic::Status> {
+        let oldest = self.sequence + 1 - self.history.len() as u64;
+        let from_sequence = from_sequence.max(1);
+        if from_sequence < oldest {
+            Err(tonic::Status::out_of_range(format!(
+                "History truncated, oldest kept sequence is {oldest}"
+            )))
+        } else if from_sequence > self.sequence + 1 {
+            Err(tonic::Status::invalid_argument(format!(
+                "Sequence {from_sequence} is past the last one {}",
+                self.sequence
+            )))
+        } else {
+            Ok(self
+                .history
+                .iter()
+                .skip((from_sequence - oldest) as usize)
+                .cloned()
+                .collect())
+        }
+    }
+
+    /// Sending never waits on watchers, an error only means nobody is watching.
+    fn publish(&self, event: GameEvent<R>) {
+        let _ = self.events.send(event);
+    }
+}
+
+/// Strips the updates sent to a watcher down to either their state or their delta. Deltas are
+/// only sent to watchers asking for them, following the update they apply to, and never more
+/// than `STATE_INTERVAL` times in a row.
+struct UpdateFormat {
+    deltas: bool,
+    last_sequence: Option<u64>,
+    deltas_in_a_row: u32,
+}
+
+impl UpdateFormat {
+    fn new(deltas: bool) -> Self {
+        Self {
+            deltas,
+            last_sequence: None,
+            deltas_in_a_row: 0,
+        }
+    }
+
+    fn format<R: GameRules>(&mut self, mut update: Update<R>) -> Update<R> {
+        let follows = self.last_sequence.map(|s| s + 1) == Some(update.sequence);
+        if self.deltas && follows && update.delta.is_some() && self.deltas_in_a_row < STATE_INTERVAL
+        {
+            update.state = None;
+            self.deltas_in_a_row += 1;
+        } else {
+            update.delta = None;
+            self.deltas_in_a_row = 0;
+        }
+        self.last_sequence = Some(update.sequence);
+        update
+    }
+}
+
+pub type WatchMessage<R> = Result<<R as GameRules>::WatchResponse, tonic::Status>;
+pub type PlayMessage<R> = Result<<R as GameRules>::PlayResponse, tonic::Status>;
+
+/// Counts a spectator of a game for as long as it is alive.
+struct Spectator(Arc<AtomicUsize>);
+
+impl Spectator {
+    fn new(spectators: &Arc<AtomicUsize>) -> Self {
+        spectators.fetch_add(1, Ordering::Relaxed);
+        Self(spectators.clone())
+    }
+}
+
+impl Drop for Spectator {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Forwards the events of a game to one watcher, starting with `backlog`. A watcher too slow
+/// to keep up is sent the updates it missed from the history, or the current state if they are
+/// no longer kept. What it is counted in is released when it leaves.
+fn spawn_watcher<R: GameRules>(
+    game: Weak<Mutex<ActiveGame<R>>>,
+    backlog: Vec<Update<R>>,
+    mut last_sequence: u64,
+    mut events: broadcast::Receiver<GameEvent<R>>,
+    mut format: UpdateFormat,
+    tx: mpsc::Sender<WatchMessage<R>>,
+    watching: (GaugeGuard, Option<Spectator>),
+) {
+    tokio::spawn(async move {
+        let _watching = watching;
+        let mut backlog = VecDeque::from(backlog);
+        loop {
+            while let Some(update) = backlog.pop_front() {
+                let response = R::watch_response(format.format(update));
+                if tx.send(Ok(response)).await.is_err() {
+                    return;
+                }
+            }
+            let event = tokio::select! {
+                _ = tx.closed() => return,
+                event = events.recv() => event,
+            };
+            match event {
+                Ok(GameEvent::Update(update)) => {
+                    last_sequence = update.sequence;
+                    backlog.push_back(*update);
+                }
+                Ok(GameEvent::Closed(reason)) => {
+                    let _ = tx.send(Err(reason.status())).await;
+                    return;
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => match game.upgrade() {
+                    None => return,
+                    Some(game) => {
+                        let game = game.lock().await;
+                        backlog.extend(
+                            game.events_since(last_sequence + 1)
+                                .unwrap_or_else(|_| vec![game.snapshot()]),
+                        );
+                        last_sequence = game.sequence;
+                        events = game.events.subscribe();
+                    }
+                },
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+/// Who opened a `Play` session, its moves are played as `Act` calls of theirs.
+struct Session {
+    id: Uuid,
+    token: Option<GameToken>,
+    client: Option<ClientAddr>,
+}
+
+impl Session {
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(token) = &self.token {
+            request.extensions_mut().insert(token.clone());
+        }
+        if let Some(client) = self.client {
+            request.extensions_mut().insert(client);
+        }
+        request
+    }
+}
+
+fn new_not_found_status(id: &Uuid) -> tonic::Status {
+    tonic::Status::not_found(format!("Game not found: {id}"))
+}
+
+pub fn new_storage_status(err: io::Error) -> tonic::Status {
+    tonic::Status::internal(format!("Storage error: {err}"))
+}
+
+pub fn try_parse_id(id: &str) -> Result<Uuid, tonic::Status> {
+    Uuid::from_str(id).map_err(|err| tonic::Status::invalid_argument(format!("Invalid id: {err}")))
+}
+
+/// Limits of the games of a service, each game service has its own.
+pub struct ServiceLimits {
+    pub max_games: usize,
+    pub idle_timeout: Duration,
+    /// Updates buffered for each watcher, a watcher further behind is resynchronized.
+    pub watch_channel_size: usize,
+    /// Rate of the calls of each client creating or joining games.
+    pub create_rate: RateLimit,
+    /// Rate of the moves of each client, over all their games.
+    pub act_rate: RateLimit,
+    pub max_watchers: usize,
+}
+
+impl Default for ServiceLimits {
+    fn default() -> Self {
+        Self {
+            max_games: 1000,
+            idle_timeout: Duration::from_secs(30 * 60),
+            watch_channel_size: DEFAULT_WATCH_CHANNEL_SIZE,
+            create_rate: RateLimit {
+                per_second: 1.0,
+                burst: 10,
+            },
+            act_rate: RateLimit {
+                per_second: 20.0,
+                burst: 40,
+            },
+            max_watchers: 32,
+        }
+    }
+}
+
+/// The games of one kind, with what every game needs whatever its rules: ids, tokens, storage,
+/// watchers and expiry. Each game sits behind its own lock, so the map itself is only locked long
+/// enough to find, add or remove a game.
+pub struct GameRegistry<R: GameRules> {
+    games: RwLock<HashMap<Uuid, GameHandle<R>>>,
+    /// Games which ended without being won, kept for `idle_timeout` from their end so that their
+    /// owner may still look at their deal, not stored.
+    ended: RwLock<HashMap<Uuid, EndedGame<R>>>,
+    /// Every recorded result, kept in memory to answer statistics queries.
+    results: RwLock<Vec<R::Result>>,
+    /// Hashes of the tokens of the registered players, proving requests naming them are theirs.
+    players: RwLock<HashMap<String, TokenHash>>,
+    /// Set once the server shuts down, games can then neither be created nor played.
+    shutting_down: AtomicBool,
+    metrics: Arc<Metrics>,
+    create_limiter: RateLimiter,
+    act_limiter: RateLimiter,
+    limits: ServiceLimits,
+    storage: Box<dyn GameStorage<R>>,
+}
+
+impl<R: GameRules> GameRegistry<R> {
+    pub fn new(
+        limits: ServiceLimits,
+        storage: Box<dyn GameStorage<R>>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            games: RwLock::default(),
+            ended: RwLock::default(),
+            results: RwLock::default(),
+            players: RwLock::default(),
+            shutting_down: AtomicBool::new(false),
+            metrics,
+            create_limiter: RateLimiter::new(limits.create_rate),
+            act_limiter: RateLimiter::new(limits.act_rate),
+            limits,
+            storage,
+        }
+    }
+
+    pub fn limits(&self) -> &ServiceLimits {
+        &self.limits
+    }
+
+    /// Counts a call creating or joining a game against the rate of its client.
+    pub fn check_create<T>(&self, request: &tonic::Request<T>) -> Result<(), tonic::Status> {
+        self.create_limiter.check(request)
+    }
+
+    fn check_serving(&self) -> Result<(), tonic::Status> {
+        if self.shutting_down.load(Ordering::Acquire) {
+            Err(tonic::Status::unavailable("Server shutting down"))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn find_game(&self, id: &Uuid) -> Result<GameHandle<R>, tonic::Status> {
+        self.check_serving()?;
+        match self.games.read().await.get(id) {
+            None => Err(new_not_found_status(id)),
+            Some(game) => Ok(game.clone()),
+        }
+    }
+
+    /// A game which ended without being won, destroyed or expired, for a while after it did.
+    pub async fn find_ended_game(&self, id: &Uuid) -> Result<EndedGame<R>, tonic::Status> {
+        self.check_serving()?;
+        match self.ended.read().await.get(id) {
+            None => Err(new_not_found_status(id)),
+            Some(game) => Ok(game.clone()),
+        }
+    }
+
+    pub async fn games(&self) -> Vec<(Uuid, GameHandle<R>)> {
+        self.games
+            .read()
+            .await
+            .iter()
+            .map(|(id, game)| (*id, game.clone()))
+            .collect()
+    }
+
+    pub async fn results(&self) -> RwLockReadGuard<'_, Vec<R::Result>> {
+        self.results.read().await
+    }
+
+    /// Claims the name `player`, answering the token requests naming it must then be sent with.
+    pub async fn register_player(&self, player: &str) -> Result<String, tonic::Status> {
+        self.check_serving()?;
+        let mut players = self.players.write().await;
+        if players.contains_key(player) {
+            return Err(tonic::Status::already_exists(format!(
+                "Player already registered: {player}"
+            )));
+        }
+        let token = auth::new_token();
+        let hash = TokenHash::of(&token);
+        // Stored under the lock, so a name is never given to two players
+        self.storage
+            .register_player(player, &hash)
+            .await
+            .map_err(new_storage_status)?;
+        players.insert(player.to_owned(), hash);
+        Ok(token)
+    }
+
+    /// Checks that `request`, which names `player`, was sent with its token.
+    pub async fn check_player<T>(
+        &self,
+        request: &tonic::Request<T>,
+        player: &str,
+    ) -> Result<(), tonic::Status> {
+        auth::check_player(request, player, self.players.read().await.get(player))
+    }
+
+    /// Restores every game, result and player kept in storage, typically after a restart.
+    pub async fn load_games(&self) -> io::Result<usize> {
+        let results = self.storage.load_results().await?;
+        let players = self.storage.load_players().await?;
+        let records = self.storage.load_all().await?;
+        *self.results.write().await = results;
+        self.players.write().await.extend(players);
+        let mut games = self.games.write().await;
+        let mut restored = 0;
+        for (id, record) in records {
+            match ActiveGame::restore(record, self.limits.watch_channel_size).await {
+                Ok(game) => {
+                    games.insert(id, Arc::new(Mutex::new(game)));
+                    restored += 1;
+                }
+                Err(e) => tracing::warn!("Skipping invalid game {id}: {e}"),
+            }
+        }
+        self.metrics.active_games.add(restored as i64);
+        Ok(restored)
+    }
+
+    /// Deals a new game for `player`, if any, and makes it available within the limit of games.
+    /// Watching it needs a spectator token when it is `private`.
+    pub async fn add_game(
+        &self,
+        setup: R::Setup,
+        player: Option<String>,
+        private: bool,
+        attachment: R::Attachment,
+    ) -> Result<Created<R>, tonic::Status> {
+        let tokens = IssuedTokens::new(private);
+        let record = GameRecord {
+            setup,
+            tokens: tokens.hashes(),
+            player,
+            created_at: SystemTime::now(),
+            actions: Vec::new(),
+        };
+        let id = Uuid::new_v4();
+        tracing::Span::current().record("game_id", tracing::field::display(&id));
+        let mut game = ActiveGame::new(&record, self.limits.watch_channel_size);
+        game.attachment = attachment;
+        let state = game.view();
+        let version = game.version;
+        {
+            let mut games = self.games.write().await;
+            // Checked under the lock, so no game is added once the games are closed
+            self.check_serving()?;
+            if games.len() >= self.limits.max_games {
+                return Err(tonic::Status::resource_exhausted(format!(
+                    "Too many games: {}",
+                    self.limits.max_games
+                )));
+            }
+            games.insert(id, Arc::new(Mutex::new(game)));
+            self.metrics.active_games.inc();
+        }
+        // Nobody knows the id yet, so the game can be stored without holding any lock
+        if let Err(e) = self.storage.create(&id, &record).await {
+            self.remove_game(&id).await;
+            return Err(new_storage_status(e));
+        }
+        Ok(Created {
+            id,
+            state,
+            version,
+            tokens,
+        })
+    }
+
+    async fn remove_game(&self, id: &Uuid) -> Option<GameHandle<R>> {
+        let game = self.games.write().await.remove(id);
+        if game.is_some() {
+            self.metrics.active_games.dec();
+        }
+        game
+    }
+
+    /// Failing to store a result does not undo the end of the game, it is only left out of the
+    /// statistics after a restart.
+    async fn record_result(&self, player: &str, result: R::Result) {
+        if let Err(e) = self.storage.record_result(&result).await {
+            tracing::error!("Failed to store result of {player}: {e}");
+        }
+        self.results.write().await.push(result);
+    }
+
+    /// Records a game that ends before being won as lost, and keeps its deal a while longer.
+    async fn close_game(&self, id: Uuid, game: &GameHandle<R>, reason: CloseReason) {
+        let game = game.lock().await;
+        if !game.victory {
+            if let (Some(result), Some(player)) = (R::result(&game), &game.player) {
+                self.record_result(player, result).await;
+            }
+            let ended = EndedGame {
+                setup: game.setup.clone(),
+                tokens: game.tokens.clone(),
+                ended_at: SystemTime::now(),
+            };
+            self.ended.write().await.insert(id, ended);
+        }
+        game.publish(GameEvent::Closed(reason));
+    }
+
+    /// Removes a game for good, on behalf of its owner.
+    pub async fn destroy_game<T>(
+        &self,
+        request: &tonic::Request<T>,
+        id: &Uuid,
+    ) -> Result<(), tonic::Status> {
+        let handle = self.find_game(id).await?;
+        {
+            let game = handle.lock().await;
+            game.tokens.check_owner(request)?;
+            // A game which cannot be removed from storage is kept, rather than coming back after
+            // a restart with its watchers gone
+            self.storage.remove(id).await.map_err(new_storage_status)?;
+            if self.remove_game(id).await.is_none() {
+                return Err(new_not_found_status(id));
+            }
+        }
+        self.close_game(*id, &handle, CloseReason::Destroyed).await;
+        Ok(())
+    }
+
+    /// Plays a move on behalf of the owner of its game.
+    pub async fn play_move(
+        &self,
+        request: tonic::Request<Move<R>>,
+    ) -> Result<Played<R>, tonic::Status> {
+        self.act_limiter.check(&request)?;
+        let id = try_parse_id(&request.get_ref().id)?;
+        let game = self.find_game(&id).await?;
+        let mut game = game.lock().await;
+        game.tokens.check_owner(&request)?;
+        let request = request.into_inner();
+        if request.idempotency_key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+            return Err(tonic::Status::invalid_argument(format!(
+                "Idempotency key longer than {MAX_IDEMPOTENCY_KEY_LEN} bytes"
+            )));
+        }
+        let proto_action = match request.action {
+            None => {
+                return Err(solitaire_grpc::bad_request(vec![
+                    solitaire_grpc::rpc::bad_request::FieldViolation {
+                        field: "action".to_owned(),
+                        description: "is required".to_owned(),
+                    },
+                ]))
+            }
+            Some(proto_action) => proto_action,
+        };
+        let action = R::action(&proto_action)?;
+        tracing::Span::current().record("action", tracing::field::display(&action));
+        // Sent again, the move already changed the version it expects
+        if let Some(played) = game.played_response(&request.idempotency_key) {
+            return Ok(played);
+        }
+        if let Some(expected) = request.expected_version {
+            if expected != game.version {
+                self.metrics.failed_move("stale version");
+                return Err(tonic::Status::aborted(format!(
+                    "Game is at version {}, expected {expected}",
+                    game.version
+                )));
+            }
+        }
+        // Play on a copy so the game is only changed once the move is persisted
+        let mut next_state = game.state.clone();
+        let outcome = R::act(&mut next_state, action.clone()).await;
+        if let Outcome::Refused(s) = outcome {
+            self.metrics.failed_move(&s);
+            return Err(tonic::Status::failed_precondition(format!(
+                "Invalid move: {s}"
+            )));
+        }
+        self.storage
+            .append_action(&id, &action)
+            .await
+            .map_err(new_storage_status)?;
+        let previous = game.view();
+        game.state = next_state;
+        game.actions.push(action);
+        game.last_activity = SystemTime::now();
+        game.moves += 1;
+        game.version += 1;
+        self.metrics.actions.inc();
+        let won = !game.victory && matches!(outcome, Outcome::Won);
+        game.victory |= won;
+        if won {
+            self.metrics.victories.inc();
+            if let (Some(result), Some(player)) = (R::result(&game), &game.player) {
+                self.record_result(player, result).await;
+            }
+        }
+        R::played(&game);
+        let state = game.view();
+        game.publish_update(proto_action, &previous, state.clone());
+        let played = Played {
+            victory: game.victory,
+            state,
+            version: game.version,
+        };
+        if !request.idempotency_key.is_empty() {
+            game.remember_played(request.idempotency_key, played.clone());
+        }
+        Ok(played)
+    }
+
+    /// Sends the updates of `game` to a new watcher, from `from_sequence` or the current state,
+    /// as deltas when it asks for them. Only spectators count towards `max_watchers`, the
+    /// sessions of the owner are never turned away.
+    /// Subscribing while holding the game lock guarantees no update is missed after the backlog.
+    fn watch_game(
+        &self,
+        handle: &GameHandle<R>,
+        game: &ActiveGame<R>,
+        from_sequence: Option<u64>,
+        deltas: bool,
+        spectator: bool,
+    ) -> Result<mpsc::Receiver<WatchMessage<R>>, tonic::Status> {
+        if spectator && game.spectators.load(Ordering::Relaxed) >= self.limits.max_watchers {
+            return Err(solitaire_grpc::retry_status(
+                tonic::Code::ResourceExhausted,
+                format!("Too many watchers: {}", self.limits.max_watchers),
+                WATCHER_RETRY_DELAY,
+            ));
+        }
+        let backlog = match from_sequence {
+            None => vec![game.snapshot()],
+            Some(from_sequence) => game.events_since(from_sequence)?,
+        };
+        let (tx, rx) = mpsc::channel(self.limits.watch_channel_size);
+        spawn_watcher(
+            Arc::downgrade(handle),
+            backlog,
+            game.sequence,
+            game.events.subscribe(),
+            UpdateFormat::new(deltas),
+            tx,
+            (
+                GaugeGuard::new(&self.metrics.watchers),
+                spectator.then(|| Spectator::new(&game.spectators)),
+            ),
+        );
+        Ok(rx)
+    }
+
+    /// Sends the updates of a game to a new watcher, who needs a spectator token for private
+    /// games.
+    pub async fn watch(
+        &self,
+        request: tonic::Request<Watch>,
+    ) -> Result<mpsc::Receiver<WatchMessage<R>>, tonic::Status> {
+        let id = try_parse_id(&request.get_ref().id)?;
+        let handle = self.find_game(&id).await?;
+        let game = handle.lock().await;
+        game.tokens.check_spectator(&request)?;
+        let watch = request.into_inner();
+        self.watch_game(&handle, &game, watch.from_sequence, watch.deltas, true)
+    }
+
+    /// Opens a `Play` session on the game named by the first of `requests`, for its owner. The
+    /// moves of the session are then played and the updates of the game sent back, until either
+    /// side goes away or the game is closed.
+    pub async fn open_session<S>(
+        self: &Arc<Self>,
+        request: tonic::Request<S>,
+    ) -> Result<mpsc::Receiver<PlayMessage<R>>, tonic::Status>
+    where
+        S: Stream<Item = Result<R::PlayRequest, tonic::Status>> + Send + Unpin + 'static,
+    {
+        let token = request.extensions().get::<GameToken>().cloned();
+        let client = limits::client_of(&request);
+        let mut requests = request.into_inner();
+        let open = match requests.next().await.transpose()?.map(R::session_request) {
+            Some(SessionRequest::Open(open)) => open,
+            _ => {
+                return Err(tonic::Status::invalid_argument(
+                    "The first message of a session must open it",
+                ))
+            }
+        };
+        tracing::Span::current().record("game_id", tracing::field::display(&open.id));
+        let id = try_parse_id(&open.id)?;
+        let session = Session { id, token, client };
+        let handle = self.find_game(&id).await?;
+        let updates = {
+            let game = handle.lock().await;
+            game.tokens.check_owner(&session.request(()))?;
+            self.watch_game(&handle, &game, open.from_sequence, open.deltas, false)?
+        };
+        let (tx, rx) = mpsc::channel(self.limits.watch_channel_size);
+        let registry = self.clone();
+        tokio::spawn(async move { registry.run_session(session, requests, updates, tx).await });
+        Ok(rx)
+    }
+
+    async fn run_session<S>(
+        &self,
+        session: Session,
+        mut requests: S,
+        mut updates: mpsc::Receiver<WatchMessage<R>>,
+        tx: mpsc::Sender<PlayMessage<R>>,
+    ) where
+        S: Stream<Item = Result<R::PlayRequest, tonic::Status>> + Unpin,
+    {
+        loop {
+            let event = tokio::select! {
+                _ = tx.closed() => return,
+                update = updates.recv() => match update {
+                    None => return,
+                    Some(update) => update.map(SessionEvent::Update),
+                },
+                request = requests.next() => match request.transpose() {
+                    Ok(None) | Err(_) => return,
+                    Ok(Some(request)) => self.answer_session(&session, request).await,
+                },
+            };
+            let closed = event.is_err();
+            if tx.send(event.map(R::session_event)).await.is_err() || closed {
+                return;
+            }
+        }
+    }
+
+    /// Answers a message of a session after the first one, failing when the session must end.
+    async fn answer_session(
+        &self,
+        session: &Session,
+        request: R::PlayRequest,
+    ) -> Result<SessionEvent<R>, tonic::Status> {
+        match R::session_request(request) {
+            SessionRequest::Move {
+                request_id,
+                action,
+                expected_version,
+                idempotency_key,
+            } => {
+                let request = session.request(Move {
+                    id: session.id.to_string(),
+                    action,
+                    expected_version,
+                    idempotency_key,
+                });
+                let span = tracing::info_span!(
+                    "act",
+                    game_id = %session.id,
+                    action = tracing::field::Empty
+                );
+                let outcome = self.play_move(request).instrument(span).await;
+                Ok(SessionEvent::Result {
+                    request_id,
+                    outcome,
+                })
+            }
+            SessionRequest::Snapshot => {
+                let game = self.find_game(&session.id).await?;
+                let snapshot = game.lock().await.snapshot();
+                Ok(SessionEvent::Update(R::watch_response(snapshot)))
+            }
+            SessionRequest::Open(_) | SessionRequest::Empty => Err(
+                tonic::Status::invalid_argument("A session is only opened once, then takes moves"),
+            ),
+        }
+    }
+
+    /// Destroys every game nobody acted on for longer than the idle timeout.
+    pub async fn reap_idle_games(&self) {
+        self.create_limiter.prune();
+        self.act_limiter.prune();
+        let mut games = self.games.write().await;
+        let (expired, kept): (HashMap<_, _>, HashMap<_, _>) = std::mem::take(&mut *games)
+            .into_iter()
+            .partition(|(_, game)| {
+                // A game locked by a move in progress is obviously not idle
+                game.try_lock()
+                    .map(|game| game.is_idle(self.limits.idle_timeout))
+                    .unwrap_or(false)
+            });
+        *games = kept;
+        self.metrics.active_games.sub(expired.len() as i64);
+        std::mem::drop(games);
+        let now = SystemTime::now();
+        self.ended.write().await.retain(|_, game| {
+            now.duration_since(game.ended_at).unwrap_or_default() < self.limits.idle_timeout
+        });
+        for (id, game) in expired {
+            if let Err(e) = self.storage.remove(&id).await {
+                tracing::error!("Failed to remove expired game {id}: {e}");
+            }
+            self.close_game(id, &game, CloseReason::Expired).await;
+        }
+    }
+
+    /// Stops accepting new games and moves, and closes every game. The games are kept in storage
+    /// to be restored on the next start, and also written to `snapshot` when given. Watchers are
+    /// told the server is going away, which ends their streams.
+    pub async fn shut_down(&self, snapshot: Option<&dyn GameStorage<R>>) {
+        let games = {
+            let mut games = self.games.write().await;
+            self.shutting_down.store(true, Ordering::Release);
+            self.metrics.active_games.sub(games.len() as i64);
+            std::mem::take(&mut *games)
+        };
+        for (id, game) in games {
+            let game = game.lock().await;
+            if let Some(snapshot) = snapshot {
+                if let Err(e) = snapshot.create(&id, &game.record()).await {
+                    tracing::error!("Failed to snapshot game {id}: {e}");
+                }
+            }
+            game.publish(GameEvent::Closed(CloseReason::ShuttingDown));
+        }
+    }
+}
+
+/// What the server does with each game service, whatever its games: restore them on start, reap
+/// the idle ones while running and close them on shutdown.
+#[tonic::async_trait]
+pub trait GameService: Send + Sync {
+    /// Name of the gRPC service, as reported by the health service.
+    fn name(&self) -> &'static str;
+    async fn restore(&self) -> io::Result<usize>;
+    async fn reap(&self);
+    /// Writes the games to `snapshot`, when given, after closing them. Every service stores its
+    /// games in files of its own.
+    async fn close(&self, snapshot: Option<&Path>);
+}
+
+pub async fn run_reaper(services: Vec<Arc<dyn GameService>>) {
+    let mut interval = tokio::time::interval(REAPER_INTERVAL);
+    loop {
+        interval.tick().await;
+        for service in services.iter() {
+            service.reap().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::GameToken;
+    use crate::storage::MemoryStorage;
+
+    /// Counts up to a target, each move adding to the count without going past it.
+    struct Counter;
+
+    #[tonic::async_trait]
+    impl GameRules for Counter {
+        type Setup = u32;
+        type Game = (u32, u32);
+        type Action = u32;
+        type Attachment = ();
+        type Result = ();
+        type ProtoAction = u32;
+        type State = u32;
+        type Delta = u32;
+        type WatchResponse = Update<Self>;
+        type PlayRequest = SessionRequest<Self>;
+        type PlayResponse = SessionEvent<Self>;
+
+        fn deal(target: &u32) -> (u32, u32) {
+            (0, *target)
+        }
+
+        async fn act(game: &mut (u32, u32), action: u32) -> Outcome {
+            match game.0 + action {
+                count if count > game.1 => Outcome::Refused("Past the target".to_owned()),
+                count => {
+                    game.0 = count;
+                    if count == game.1 {
+                        Outcome::Won
+                    } else {
+                        Outcome::Played
+                    }
+                }
+            }
+        }
+
+        fn action(action: &u32) -> Result<u32, tonic::Status> {
+            Ok(*action)
+        }
+
+        fn view(game: &(u32, u32)) -> u32 {
+            game.0
+        }
+
+        fn delta(from: &u32, to: &u32) -> u32 {
+            to - from
+        }
+
+        fn checksum(state: &u32) -> u64 {
+            *state as u64
+        }
+
+        fn watch_response(update: Update<Self>) -> Update<Self> {
+            update
+        }
+
+        fn session_request(request: SessionRequest<Self>) -> SessionRequest<Self> {
+            request
+        }
+
+        fn session_event(event: SessionEvent<Self>) -> SessionEvent<Self> {
+            event
+        }
+
+        fn result(_game: &ActiveGame<Self>) -> Option<()> {
+            None
+        }
+    }
+
+    fn add(created: &Created<Counter>, action: u32) -> tonic::Request<Move<Counter>> {
+        let mut request = tonic::Request::new(Move {
+            id: created.id.to_string(),
+            action: Some(action),
+            expected_version: None,
+            idempotency_key: String::new(),
+        });
+        request
+            .extensions_mut()
+            .insert(GameToken(created.tokens.owner.clone()));
+        request
+    }
+
+    #[tokio::test]
+    async fn games_of_any_rules_are_hosted() {
+        let registry = GameRegistry::<Counter>::new(
+            ServiceLimits::default(),
+            Box::new(MemoryStorage::default()),
+            Arc::default(),
+        );
+        let created = registry.add_game(5, None, false, ()).await.unwrap();
+        assert_eq!(created.state, 0);
+
+        let played = registry.play_move(add(&created, 2)).await.unwrap();
+        assert_eq!(
+            (played.state, played.version, played.victory),
+            (2, 1, false)
+        );
+        let err = registry.play_move(add(&created, 4)).await.err().unwrap();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+        let mut updates = {
+            let handle = registry.find_game(&created.id).await.unwrap();
+            let game = handle.lock().await;
+            registry
+                .watch_game(&handle, &game, Some(1), true, true)
+                .unwrap()
+        };
+        let first = updates.recv().await.unwrap().unwrap();
+        assert_eq!((first.state, first.delta), (Some(2), None));
+        let played = registry.play_move(add(&created, 3)).await.unwrap();
+        assert!(played.victory);
+        let second = updates.recv().await.unwrap().unwrap();
+        assert_eq!((second.state, second.delta), (None, Some(3)));
+        assert_eq!(second.checksum, 5);
+
+        // The moves are stored, and replayed by the next registry
+        let snapshot = MemoryStorage::default();
+        registry.shut_down(Some(&snapshot)).await;
+        let status = updates.recv().await.unwrap().err().unwrap();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        let restored = GameRegistry::<Counter>::new(
+            ServiceLimits::default(),
+            Box::new(snapshot),
+            Arc::default(),
+        );
+        assert_eq!(restored.load_games().await.unwrap(), 1);
+        let handle = restored.find_game(&created.id).await.unwrap();
+        let game = handle.lock().await;
+        assert_eq!((game.view(), game.version, game.victory), (5, 2, true));
+    }
+
+    fn updated(event: SessionEvent<Counter>) -> Update<Counter> {
+        match event {
+            SessionEvent::Update(update) => update,
+            SessionEvent::Result { .. } => panic!("Expected an update"),
+        }
+    }
+
+    fn result(event: SessionEvent<Counter>) -> (u64, Result<Played<Counter>, tonic::Status>) {
+        match event {
+            SessionEvent::Result {
+                request_id,
+                outcome,
+            } => (request_id, outcome),
+            SessionEvent::Update(_) => panic!("Expected a move result"),
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_are_played_with_any_rules() {
+        let registry = Arc::new(GameRegistry::<Counter>::new(
+            ServiceLimits::default(),
+            Box::new(MemoryStorage::default()),
+            Arc::default(),
+        ));
+        let created = registry.add_game(5, None, false, ()).await.unwrap();
+        let open = |token: Option<&str>| {
+            let (requests, rx) = mpsc::channel(4);
+            let open = SessionRequest::Open(Watch {
+                id: created.id.to_string(),
+                from_sequence: None,
+                deltas: true,
+            });
+            requests.try_send(Ok(open)).unwrap();
+            let mut request = tonic::Request::new(tokio_stream::wrappers::ReceiverStream::new(rx));
+            if let Some(token) = token {
+                request.extensions_mut().insert(GameToken(token.to_owned()));
+            }
+            (requests, request)
+        };
+        let (_, request) = open(None);
+        let status = registry.open_session(request).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let (requests, request) = open(Some(&created.tokens.owner));
+        let mut events = registry.open_session(request).await.unwrap();
+        let snapshot = updated(events.recv().await.unwrap().unwrap());
+        assert_eq!((snapshot.state, snapshot.version), (Some(0), 0));
+
+        let add = |request_id, action| SessionRequest::Move {
+            request_id,
+            action: Some(action),
+            expected_version: None,
+            idempotency_key: String::new(),
+        };
+        requests.send(Ok(add(1, 2))).await.unwrap();
+        let (request_id, played) = result(events.recv().await.unwrap().unwrap());
+        assert_eq!(request_id, 1);
+        assert_eq!(played.unwrap().state, 2);
+        let update = updated(events.recv().await.unwrap().unwrap());
+        assert_eq!((update.delta, update.version), (Some(2), 1));
+
+        // Refused moves are answered, the session goes on
+        requests.send(Ok(add(2, 4))).await.unwrap();
+        let (request_id, refused) = result(events.recv().await.unwrap().unwrap());
+        assert_eq!(request_id, 2);
+        assert_eq!(
+            refused.err().unwrap().code(),
+            tonic::Code::FailedPrecondition
+        );
+        requests.send(Ok(SessionRequest::Snapshot)).await.unwrap();
+        let snapshot = updated(events.recv().await.unwrap().unwrap());
+        assert_eq!((snapshot.state, snapshot.version), (Some(2), 1));
+
+        requests.send(Ok(SessionRequest::Empty)).await.unwrap();
+        let status = events.recv().await.unwrap().err().unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(events.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sessions_leave_room_for_spectators() {
+        let registry = Arc::new(GameRegistry::<Counter>::new(
+            ServiceLimits {
+                max_watchers: 2,
+                ..ServiceLimits::default()
+            },
+            Box::new(MemoryStorage::default()),
+            Arc::default(),
+        ));
+        let created = registry.add_game(5, None, false, ()).await.unwrap();
+        let watch = || Watch {
+            id: created.id.to_string(),
+            from_sequence: None,
+            deltas: false,
+        };
+        let (requests, rx) = mpsc::channel(1);
+        requests
+            .try_send(Ok(SessionRequest::Open(watch())))
+            .unwrap();
+        let mut request = tonic::Request::new(tokio_stream::wrappers::ReceiverStream::new(rx));
+        request
+            .extensions_mut()
+            .insert(GameToken(created.tokens.owner.clone()));
+        let _session = registry.open_session(request).await.unwrap();
+
+        let first = registry.watch(tonic::Request::new(watch())).await.unwrap();
+        let _second = registry.watch(tonic::Request::new(watch())).await.unwrap();
+        let err = registry
+            .watch(tonic::Request::new(watch()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+
+        // A spectator leaving makes room for another one
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while registry.watch(tonic::Request::new(watch())).await.is_err() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,1773 @@
+use crate::daily::{self, DailySecret, DateSource};
+use crate::metrics::Metrics;
+use crate::race::{self, Race};
+use crate::registry::{self, GameRegistry, GameService, ServiceLimits};
+use crate::solitaire::{Deal, SolitaireRules};
+use crate::stats::{self, ResultFilter};
+use crate::storage::{FileStorage, GameStorage};
+use boards::random_engine::{DefaultRandomEngine, XorShifEngine};
+use chrono::NaiveDate;
+use solitaire_backend::{shuffled_deck, GameOptions};
+use solitaire_grpc::proto::solitaire_server::{Solitaire, SolitaireServer};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+type WatchMessage = registry::WatchMessage<SolitaireRules>;
+type WatchMatchMessage = Result<solitaire_grpc::proto::WatchMatchResponse, tonic::Status>;
+type PlayMessage = registry::PlayMessage<SolitaireRules>;
+
+fn to_unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The `Solitaire` game service: its games are kept by the registry, the service adds what only
+/// solitaire has, daily games, matches and player statistics.
+pub struct SolitaireService {
+    games: Arc<GameRegistry<SolitaireRules>>,
+    /// Players who started the daily game of a day, they may not start it again.
+    daily_players: Mutex<HashSet<(NaiveDate, String)>>,
+    /// Matches are not stored, they only live as long as one of their games.
+    matches: RwLock<HashMap<Uuid, Arc<Race>>>,
+    date_source: Box<dyn DateSource>,
+    daily_secret: DailySecret,
+}
+
+impl SolitaireService {
+    pub fn new(
+        limits: ServiceLimits,
+        storage: Box<dyn GameStorage<SolitaireRules>>,
+        metrics: Arc<Metrics>,
+        date_source: Box<dyn DateSource>,
+        daily_secret: DailySecret,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            games: Arc::new(GameRegistry::new(limits, storage, metrics)),
+            daily_players: Mutex::default(),
+            matches: RwLock::default(),
+            date_source,
+            daily_secret,
+        })
+    }
+
+    /// Restores every game and result kept in storage, typically after a restart.
+    pub async fn load_games(&self) -> io::Result<usize> {
+        let restored = self.games.load_games().await?;
+        let mut daily_players = self.daily_players.lock().await;
+        daily_players.extend(
+            self.games
+                .results()
+                .await
+                .iter()
+                .filter_map(|r| r.daily.map(|d| (d, r.player.clone()))),
+        );
+        for (_, game) in self.games.games().await {
+            let game = game.lock().await;
+            if let Some(key) = game.setup.daily.zip(game.player.clone()) {
+                daily_players.insert(key);
+            }
+        }
+        Ok(restored)
+    }
+
+    /// Deals a new game and makes it available.
+    async fn add_game(
+        &self,
+        deal: Deal,
+        player: Option<String>,
+        private: bool,
+        race: Option<Arc<Race>>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::CreateGameResponse>, tonic::Status> {
+        let created = self.games.add_game(deal, player, private, race).await?;
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::CreateGameResponse {
+                id: created.id.to_string(),
+                config: created.state.config.clone(),
+                state: Some(created.state),
+                version: created.version,
+                owner_token: created.tokens.owner,
+                spectator_token: created.tokens.spectator,
+            },
+        ))
+    }
+
+    /// Plays the move of an `Act` request.
+    async fn play_move(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::ActRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::ActResponse>, tonic::Status> {
+        let played = self
+            .games
+            .play_move(request.map(|request| registry::Move {
+                id: request.id,
+                action: request.action,
+                expected_version: request.expected_version,
+                idempotency_key: request.idempotency_key,
+            }))
+            .await?;
+        Ok(tonic::Response::new(played.into()))
+    }
+
+    /// Checks that the player named by `request` is valid, and that the request comes from them.
+    async fn check_player<T>(
+        &self,
+        request: &tonic::Request<T>,
+        player: &str,
+    ) -> Result<(), tonic::Status> {
+        stats::validate_player(player)?;
+        self.games.check_player(request, player).await
+    }
+
+    /// Gives `player` a game in `race`, dealt like the games of the other players.
+    async fn add_racer(
+        &self,
+        race: &Arc<Race>,
+        player: String,
+    ) -> Result<solitaire_grpc::proto::CreateGameResponse, tonic::Status> {
+        race.join(&player)?;
+        let deal = Deal {
+            deck: race.deck.clone(),
+            options: race.options,
+            daily: None,
+        };
+        match self
+            .add_game(deal, Some(player.clone()), false, Some(race.clone()))
+            .await
+        {
+            Ok(response) => Ok(response.into_inner()),
+            Err(e) => {
+                race.leave(&player);
+                Err(e)
+            }
+        }
+    }
+
+    async fn find_match(&self, match_id: &str) -> Result<Arc<Race>, tonic::Status> {
+        let id = Uuid::from_str(match_id)
+            .map_err(|err| tonic::Status::invalid_argument(format!("Invalid match id: {err}")))?;
+        match self.matches.read().await.get(&id) {
+            None => Err(tonic::Status::not_found(format!("Match not found: {id}"))),
+            Some(race) => Ok(race.clone()),
+        }
+    }
+
+    /// Destroys every game nobody acted on for longer than the idle timeout.
+    async fn reap_idle_games(&self) {
+        self.games.reap_idle_games().await;
+        // Only the map is left holding the matches whose games are all gone
+        self.matches
+            .write()
+            .await
+            .retain(|_, race| Arc::strong_count(race) > 1);
+    }
+
+    /// Stops accepting new games and moves, and closes every game. The games are kept in storage
+    /// to be restored on the next start, and also written to `snapshot` when given. Watchers are
+    /// told the server is going away, which ends their streams.
+    pub async fn shut_down(&self, snapshot: Option<&dyn GameStorage<SolitaireRules>>) {
+        self.games.shut_down(snapshot).await;
+        // Dropping the matches with their games ends the streams of their watchers
+        self.matches.write().await.clear();
+    }
+}
+
+#[tonic::async_trait]
+impl GameService for SolitaireService {
+    fn name(&self) -> &'static str {
+        <SolitaireServer<SolitaireService> as tonic::transport::NamedService>::NAME
+    }
+
+    async fn restore(&self) -> io::Result<usize> {
+        self.load_games().await
+    }
+
+    async fn reap(&self) {
+        self.reap_idle_games().await
+    }
+
+    async fn close(&self, snapshot: Option<&Path>) {
+        let snapshot = match snapshot {
+            None => None,
+            Some(dir) => match FileStorage::<SolitaireRules>::new(dir.to_owned()).await {
+                Ok(storage) => Some(storage),
+                Err(e) => {
+                    tracing::error!("Failed to open snapshot {}: {e}", dir.display());
+                    None
+                }
+            },
+        };
+        self.shut_down(snapshot.as_ref().map(|s| s as &dyn GameStorage<_>))
+            .await
+    }
+}
+
+#[tonic::async_trait]
+impl Solitaire for SolitaireService {
+    #[tracing::instrument(skip_all, fields(game_id))]
+    async fn create_game(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::CreateGameRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::CreateGameResponse>, tonic::Status> {
+        self.games.check_create(&request)?;
+        let options: GameOptions = match request.get_ref().config.as_ref() {
+            None => GameOptions::default(),
+            Some(config) => config.try_into()?,
+        };
+        let deal = Deal {
+            deck: match options.seed {
+                None => shuffled_deck(&mut DefaultRandomEngine::new()),
+                Some(seed) => shuffled_deck(&mut XorShifEngine::new(seed)),
+            },
+            options,
+            daily: None,
+        };
+        let player = match request.get_ref().player.as_str() {
+            "" => None,
+            player => {
+                self.check_player(&request, player).await?;
+                Some(player.to_owned())
+            }
+        };
+        let private = request.get_ref().require_spectator_token;
+        self.add_game(deal, player, private, None).await
+    }
+
+    #[tracing::instrument(skip_all, fields(player = %request.get_ref().player, game_id))]
+    async fn create_daily_game(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::CreateDailyGameRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::CreateGameResponse>, tonic::Status> {
+        self.games.check_create(&request)?;
+        self.check_player(&request, &request.get_ref().player)
+            .await?;
+        let player = request.into_inner().player;
+        let today = self.date_source.today();
+        let options = self.daily_secret.daily_options(today);
+        let deal = Deal {
+            deck: shuffled_deck(&mut XorShifEngine::new(options.seed.unwrap())),
+            options,
+            daily: Some(today),
+        };
+        let key = (today, player);
+        if !self.daily_players.lock().await.insert(key.clone()) {
+            return Err(tonic::Status::already_exists(format!(
+                "{} already played the daily game of {today}",
+                key.1
+            )));
+        }
+        let response = self.add_game(deal, Some(key.1.clone()), false, None).await;
+        if response.is_err() {
+            self.daily_players.lock().await.remove(&key);
+        }
+        response
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_daily_results(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::GetDailyResultsRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::GetDailyResultsResponse>, tonic::Status>
+    {
+        let date = match request.get_ref().date.as_deref() {
+            None => self.date_source.today(),
+            Some(date) => daily::parse_date(date)?,
+        };
+        let results = self.games.results().await;
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::GetDailyResultsResponse {
+                date: date.to_string(),
+                results: daily::daily_results(&results, date),
+            },
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(game_id = %request.get_ref().id))]
+    async fn destroy_game(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::DestroyGameRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::DestroyGameResponse>, tonic::Status> {
+        let id = registry::try_parse_id(&request.get_ref().id)?;
+        self.games.destroy_game(&request, &id).await?;
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::DestroyGameResponse {},
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(game_id = %request.get_ref().id))]
+    async fn get_game(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::GetGameRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::GetGameResponse>, tonic::Status> {
+        let id = registry::try_parse_id(&request.get_ref().id)?;
+        let game = self.games.find_game(&id).await?;
+        let game = game.lock().await;
+        game.tokens.check_spectator(&request)?;
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::GetGameResponse {
+                state: Some(game.view()),
+                version: game.version,
+            },
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(game_id = %request.get_ref().id))]
+    async fn reveal_deal(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::RevealDealRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::RevealDealResponse>, tonic::Status> {
+        let id = registry::try_parse_id(&request.get_ref().id)?;
+        // A game is over once it is won, or once it ended without being won
+        let setup = match self.games.find_game(&id).await {
+            Ok(game) => {
+                let game = game.lock().await;
+                game.tokens.check_owner(&request)?;
+                if !game.victory {
+                    return Err(tonic::Status::failed_precondition(
+                        "The deal is revealed once the game is over",
+                    ));
+                }
+                game.setup.clone()
+            }
+            Err(status) => match self.games.find_ended_game(&id).await {
+                Ok(game) => {
+                    game.tokens.check_owner(&request)?;
+                    game.setup
+                }
+                Err(_) => return Err(status),
+            },
+        };
+        if matches!(setup.daily, Some(day) if day >= self.date_source.today()) {
+            return Err(tonic::Status::failed_precondition(
+                "The daily deal is revealed once the day is over",
+            ));
+        }
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::RevealDealResponse {
+                deck: setup.deck.iter().map(|card| card.into()).collect(),
+                seed: setup.options.seed,
+            },
+        ))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn list_games(
+        &self,
+        _request: tonic::Request<solitaire_grpc::proto::ListGamesRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::ListGamesResponse>, tonic::Status> {
+        let games = self.games.games().await;
+        let mut summaries = Vec::with_capacity(games.len());
+        for (id, game) in games {
+            let game = game.lock().await;
+            summaries.push(solitaire_grpc::proto::GameSummary {
+                id: id.to_string(),
+                created_at: to_unix_millis(game.created_at),
+                last_activity: to_unix_millis(game.last_activity),
+                moves: game.moves,
+                victory: game.victory,
+                player: game.player.clone().unwrap_or_default(),
+            });
+        }
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::ListGamesResponse { games: summaries },
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(game_id = %request.get_ref().id, action, result))]
+    async fn act(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::ActRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::ActResponse>, tonic::Status> {
+        let response = self.play_move(request).await;
+        let span = tracing::Span::current();
+        match &response {
+            Ok(_) => span.record("result", tracing::field::display("ok")),
+            Err(status) => span.record("result", tracing::field::debug(status.code())),
+        };
+        response
+    }
+
+    #[tracing::instrument(skip_all, fields(match_id, game_id))]
+    async fn create_match(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::CreateMatchRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::CreateMatchResponse>, tonic::Status> {
+        self.games.check_create(&request)?;
+        self.check_player(&request, &request.get_ref().player)
+            .await?;
+        let request = request.into_inner();
+        let options: GameOptions = match request.config.as_ref() {
+            None => GameOptions::default(),
+            Some(config) => config.try_into()?,
+        };
+        let deck = match options.seed {
+            None => shuffled_deck(&mut DefaultRandomEngine::new()),
+            Some(seed) => shuffled_deck(&mut XorShifEngine::new(seed)),
+        };
+        let id = Uuid::new_v4();
+        tracing::Span::current().record("match_id", tracing::field::display(&id));
+        let race = Arc::new(Race::new(deck, options));
+        let game = self.add_racer(&race, request.player).await?;
+        self.matches.write().await.insert(id, race);
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::CreateMatchResponse {
+                match_id: id.to_string(),
+                game: Some(game),
+            },
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(match_id = %request.get_ref().match_id, game_id))]
+    async fn join_match(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::JoinMatchRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::JoinMatchResponse>, tonic::Status> {
+        self.games.check_create(&request)?;
+        self.check_player(&request, &request.get_ref().player)
+            .await?;
+        let request = request.into_inner();
+        let race = self.find_match(&request.match_id).await?;
+        let game = self.add_racer(&race, request.player).await?;
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::JoinMatchResponse { game: Some(game) },
+        ))
+    }
+
+    type WatchMatchStream = ReceiverStream<WatchMatchMessage>;
+
+    #[tracing::instrument(skip_all, fields(match_id = %request.get_ref().match_id))]
+    async fn watch_match(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::WatchMatchRequest>,
+    ) -> Result<tonic::Response<Self::WatchMatchStream>, tonic::Status> {
+        let race = self.find_match(&request.get_ref().match_id).await?;
+        let (tx, rx) = mpsc::channel(self.games.limits().watch_channel_size);
+        race::spawn_race_watcher(&race, tx);
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[tracing::instrument(skip_all, fields(player = %request.get_ref().player))]
+    async fn register_player(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::RegisterPlayerRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::RegisterPlayerResponse>, tonic::Status> {
+        self.games.check_create(&request)?;
+        let player = request.into_inner().player;
+        stats::validate_player(&player)?;
+        let player_token = self.games.register_player(&player).await?;
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::RegisterPlayerResponse { player_token },
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(player = %request.get_ref().player))]
+    async fn get_player_stats(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::GetPlayerStatsRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::GetPlayerStatsResponse>, tonic::Status> {
+        let request = request.into_inner();
+        stats::validate_player(&request.player)?;
+        let filter = ResultFilter::from_proto(request.variant, request.period, SystemTime::now())?;
+        let results = self.games.results().await;
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::GetPlayerStatsResponse {
+                stats: Some(stats::player_stats(&results, &request.player, &filter)),
+            },
+        ))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_leaderboard(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::GetLeaderboardRequest>,
+    ) -> Result<tonic::Response<solitaire_grpc::proto::GetLeaderboardResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let filter = ResultFilter::from_proto(request.variant, request.period, SystemTime::now())?;
+        let results = self.games.results().await;
+        Ok(tonic::Response::new(
+            solitaire_grpc::proto::GetLeaderboardResponse {
+                entries: stats::leaderboard(&results, &filter, request.limit),
+            },
+        ))
+    }
+
+    type WatchStream = ReceiverStream<WatchMessage>;
+
+    #[tracing::instrument(skip_all, fields(game_id = %request.get_ref().id))]
+    async fn watch(
+        &self,
+        request: tonic::Request<solitaire_grpc::proto::WatchRequest>,
+    ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
+        let updates = self
+            .games
+            .watch(request.map(|request| registry::Watch {
+                id: request.id,
+                from_sequence: request.from_sequence,
+                deltas: request.deltas,
+            }))
+            .await?;
+        Ok(tonic::Response::new(ReceiverStream::new(updates)))
+    }
+
+    type PlayStream = ReceiverStream<PlayMessage>;
+
+    #[tracing::instrument(skip_all, fields(game_id))]
+    async fn play(
+        &self,
+        request: tonic::Request<tonic::Streaming<solitaire_grpc::proto::PlayRequest>>,
+    ) -> Result<tonic::Response<Self::PlayStream>, tonic::Status> {
+        let events = self.games.open_session(request).await?;
+        Ok(tonic::Response::new(ReceiverStream::new(events)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{GameToken, PlayerToken};
+    use crate::limits::{ClientAddr, RateLimit};
+    use crate::registry::{
+        try_parse_id, DEFAULT_WATCH_CHANNEL_SIZE, HISTORY_SIZE, MAX_IDEMPOTENCY_KEY_LEN,
+        STATE_INTERVAL,
+    };
+    use crate::storage::MemoryStorage;
+    use solitaire_grpc::proto::play_request;
+    use solitaire_grpc::proto::play_response::{self, move_result};
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn new_service() -> Arc<SolitaireService> {
+        new_service_on(Box::new(daily::SystemDateSource))
+    }
+
+    fn new_service_on(date_source: Box<dyn DateSource>) -> Arc<SolitaireService> {
+        new_measured_service(date_source, Arc::default())
+    }
+
+    fn new_measured_service(
+        date_source: Box<dyn DateSource>,
+        metrics: Arc<Metrics>,
+    ) -> Arc<SolitaireService> {
+        SolitaireService::new(
+            ServiceLimits::default(),
+            Box::new(MemoryStorage::default()),
+            metrics,
+            date_source,
+            DailySecret::new(b"test"),
+        )
+    }
+
+    /// A date the test moves forward by hand.
+    #[derive(Clone)]
+    struct FixedDate(Arc<std::sync::Mutex<NaiveDate>>);
+
+    impl DateSource for FixedDate {
+        fn today(&self) -> NaiveDate {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    struct TestGame {
+        id: String,
+        token: String,
+    }
+
+    /// Builds a request the way the interceptor hands it over when `token` is sent.
+    fn authorized<T>(message: T, token: &str) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.extensions_mut().insert(GameToken(token.to_owned()));
+        request
+    }
+
+    /// Builds a request the way the interceptor hands it over when a player token is sent.
+    fn as_player<T>(message: T, player_token: &str) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request
+            .extensions_mut()
+            .insert(PlayerToken(player_token.to_owned()));
+        request
+    }
+
+    async fn register(service: &SolitaireService, player: &str) -> String {
+        service
+            .register_player(tonic::Request::new(
+                solitaire_grpc::proto::RegisterPlayerRequest {
+                    player: player.to_owned(),
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .player_token
+    }
+
+    async fn create_game(service: &SolitaireService) -> TestGame {
+        let response = service
+            .create_game(tonic::Request::new(
+                solitaire_grpc::proto::CreateGameRequest {
+                    config: None,
+                    require_spectator_token: false,
+                    player: String::new(),
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        TestGame {
+            id: response.id,
+            token: response.owner_token,
+        }
+    }
+
+    fn draw_request(game: &TestGame) -> solitaire_grpc::proto::ActRequest {
+        solitaire_grpc::proto::ActRequest {
+            id: game.id.clone(),
+            action: Some(solitaire_backend::Action::Draw.into()),
+            expected_version: None,
+            idempotency_key: String::new(),
+        }
+    }
+
+    async fn draw(service: &SolitaireService, game: &TestGame) {
+        service
+            .act(authorized(draw_request(game), &game.token))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_games_proceed_independently() {
+        let service = new_service();
+
+        // A watcher that never reads its stream must not hold back the game it watches
+        let stalled = create_game(&service).await;
+        let _stalled_watch = service
+            .watch(tonic::Request::new(solitaire_grpc::proto::WatchRequest {
+                id: stalled.id.clone(),
+                from_sequence: None,
+                deltas: false,
+            }))
+            .await
+            .unwrap();
+
+        let mut tasks = Vec::new();
+        for _ in 0..50 {
+            let service = service.clone();
+            tasks.push(tokio::spawn(async move {
+                let game = create_game(&service).await;
+                for _ in 0..100 {
+                    draw(&service, &game).await;
+                }
+            }));
+        }
+        let stalled_service = service.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..(DEFAULT_WATCH_CHANNEL_SIZE * 4) {
+                draw(&stalled_service, &stalled).await;
+            }
+        }));
+
+        tokio::time::timeout(TEST_TIMEOUT, async {
+            for task in tasks {
+                task.await.unwrap();
+            }
+        })
+        .await
+        .expect("games were blocked by each other");
+
+        let games = service
+            .list_games(tonic::Request::new(
+                solitaire_grpc::proto::ListGamesRequest {},
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .games;
+        assert_eq!(games.len(), 51);
+        assert_eq!(
+            games.iter().map(|g| g.moves).sum::<u32>(),
+            50 * 100 + DEFAULT_WATCH_CHANNEL_SIZE as u32 * 4
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_moves_are_aborted() {
+        let metrics = Arc::new(Metrics::default());
+        let service = new_measured_service(Box::new(daily::SystemDateSource), metrics.clone());
+        let game = create_game(&service).await;
+        let act = |expected_version| {
+            service.act(authorized(
+                solitaire_grpc::proto::ActRequest {
+                    expected_version,
+                    ..draw_request(&game)
+                },
+                &game.token,
+            ))
+        };
+
+        assert_eq!(act(Some(0)).await.unwrap().into_inner().version, 1);
+        assert_eq!(act(None).await.unwrap().into_inner().version, 2);
+        let err = act(Some(1)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Aborted);
+        assert_eq!(act(Some(2)).await.unwrap().into_inner().version, 3);
+
+        assert_eq!(metrics.actions.get(), 3);
+        let stale = metrics.failed_moves.with_label_values(&["stale_version"]);
+        assert_eq!(stale.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn moves_sent_again_are_played_once() {
+        let metrics = Arc::new(Metrics::default());
+        let service = new_measured_service(Box::new(daily::SystemDateSource), metrics.clone());
+        let game = create_game(&service).await;
+        let act = |key: &str| {
+            service.act(authorized(
+                solitaire_grpc::proto::ActRequest {
+                    expected_version: Some(0),
+                    idempotency_key: key.to_owned(),
+                    ..draw_request(&game)
+                },
+                &game.token,
+            ))
+        };
+
+        let played = act("first").await.unwrap().into_inner();
+        assert_eq!(played.version, 1);
+        assert_eq!(act("first").await.unwrap().into_inner(), played);
+        let err = act("second").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Aborted);
+        assert_eq!(metrics.actions.get(), 1);
+
+        let err = act(&"k".repeat(MAX_IDEMPOTENCY_KEY_LEN + 1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn malformed_actions_name_their_fields() {
+        use solitaire_grpc::proto::action::{self, build_foundation, build_tableau};
+        let metrics = Arc::new(Metrics::default());
+        let service = new_measured_service(Box::new(daily::SystemDateSource), metrics.clone());
+        let game = create_game(&service).await;
+        let foundation = |source| {
+            Some(action::Action::BuildFoundation(action::BuildFoundation {
+                source,
+            }))
+        };
+        let from_tableau = |index, size, destination_index| {
+            Some(action::Action::BuildTableau(action::BuildTableau {
+                source: Some(build_tableau::Source::Tableau(build_tableau::Tableau {
+                    index,
+                    size,
+                })),
+                destination_index,
+            }))
+        };
+        let cases = [
+            (None, vec!["action"]),
+            (foundation(None), vec!["action.build_foundation.source"]),
+            (
+                foundation(Some(build_foundation::Source::Tableau(
+                    build_foundation::Tableau { index: 7 },
+                ))),
+                vec!["action.build_foundation.tableau.index"],
+            ),
+            (
+                Some(action::Action::BuildTableau(action::BuildTableau {
+                    source: None,
+                    destination_index: 7,
+                })),
+                vec![
+                    "action.build_tableau.destination_index",
+                    "action.build_tableau.source",
+                ],
+            ),
+            (
+                from_tableau(u32::MAX, 1, 0),
+                vec!["action.build_tableau.tableau.index"],
+            ),
+            (
+                from_tableau(2, 2, 2),
+                vec!["action.build_tableau.destination_index"],
+            ),
+            (
+                from_tableau(0, 0, 1),
+                vec!["action.build_tableau.tableau.size"],
+            ),
+            (
+                from_tableau(0, 14, 1),
+                vec!["action.build_tableau.tableau.size"],
+            ),
+        ];
+        for (action, fields) in cases {
+            let request = solitaire_grpc::proto::ActRequest {
+                action: Some(solitaire_grpc::proto::Action { action }),
+                ..draw_request(&game)
+            };
+            let err = service
+                .act(authorized(request, &game.token))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+            let violations = solitaire_grpc::field_violations(&err);
+            assert_eq!(
+                violations
+                    .iter()
+                    .map(|v| v.field.as_str())
+                    .collect::<Vec<_>>(),
+                fields
+            );
+        }
+
+        // A request without an action at all
+        let request = solitaire_grpc::proto::ActRequest {
+            action: None,
+            ..draw_request(&game)
+        };
+        let err = service
+            .act(authorized(request, &game.token))
+            .await
+            .unwrap_err();
+        assert_eq!(solitaire_grpc::field_violations(&err)[0].field, "action");
+        assert_eq!(metrics.actions.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn games_are_dealt_from_config() {
+        let service = new_service();
+        let config = solitaire_grpc::proto::GameConfig {
+            variant: solitaire_grpc::proto::Variant::Thoughtful.into(),
+            draw_count: 3,
+            redeal_limit: Some(0),
+            scoring: solitaire_grpc::proto::ScoringMode::Vegas.into(),
+            seed: Some(7),
+            auto_foundation: false,
+        };
+        let create = |config| {
+            service.create_game(tonic::Request::new(
+                solitaire_grpc::proto::CreateGameRequest {
+                    config: Some(config),
+                    require_spectator_token: false,
+                    player: String::new(),
+                },
+            ))
+        };
+
+        let first = create(config.clone()).await.unwrap().into_inner();
+        let second = create(config.clone()).await.unwrap().into_inner();
+        // The seed gives the deal away, it is never shown
+        let shown = solitaire_grpc::proto::GameConfig {
+            seed: None,
+            ..config.clone()
+        };
+        assert_eq!(first.config.as_ref(), Some(&shown));
+        let state = first.state.unwrap();
+        assert_eq!(state, second.state.unwrap());
+        assert_eq!(state.config, Some(shown));
+        assert_eq!(state.score, Some(-52));
+        assert!(state.tableaus.iter().all(|t| t.downfaced_len == 0));
+        assert_eq!(state.draw_pile_size, 24);
+
+        let game = TestGame {
+            id: first.id.clone(),
+            token: first.owner_token.clone(),
+        };
+        draw(&service, &game).await;
+        let state = service
+            .get_game(tonic::Request::new(solitaire_grpc::proto::GetGameRequest {
+                id: first.id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .state
+            .unwrap();
+        assert_eq!(state.draw_pile_size, 21);
+
+        let err = create(solitaire_grpc::proto::GameConfig {
+            draw_count: 2,
+            ..config.clone()
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        let err = create(solitaire_grpc::proto::GameConfig {
+            seed: Some(0),
+            ..config
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn games_need_their_tokens() {
+        let service = new_service();
+        let game = create_game(&service).await;
+        let other = create_game(&service).await;
+
+        let err = service
+            .act(tonic::Request::new(draw_request(&game)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        let err = service
+            .act(authorized(draw_request(&game), &other.token))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let destroy = |token: &str| {
+            service.destroy_game(authorized(
+                solitaire_grpc::proto::DestroyGameRequest {
+                    id: game.id.clone(),
+                },
+                token,
+            ))
+        };
+        let err = destroy(&other.token).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        assert!(destroy(&game.token).await.is_ok());
+
+        let private = service
+            .create_game(tonic::Request::new(
+                solitaire_grpc::proto::CreateGameRequest {
+                    config: None,
+                    require_spectator_token: true,
+                    player: String::new(),
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let watch_request = || solitaire_grpc::proto::WatchRequest {
+            id: private.id.clone(),
+            from_sequence: None,
+            deltas: false,
+        };
+        let err = service
+            .watch(tonic::Request::new(watch_request()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        let spectator = private.spectator_token.as_ref().unwrap();
+        assert!(service
+            .watch(authorized(watch_request(), spectator))
+            .await
+            .is_ok());
+        assert!(service
+            .watch(authorized(watch_request(), &private.owner_token))
+            .await
+            .is_ok());
+        let err = service
+            .act(authorized(
+                solitaire_grpc::proto::ActRequest {
+                    id: private.id.clone(),
+                    action: Some(solitaire_backend::Action::Draw.into()),
+                    expected_version: None,
+                    idempotency_key: String::new(),
+                },
+                spectator,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn finished_games_are_recorded() {
+        let service = new_service();
+        let alice = register(&service, "alice").await;
+        let response = service
+            .create_game(as_player(
+                solitaire_grpc::proto::CreateGameRequest {
+                    config: None,
+                    require_spectator_token: false,
+                    player: "alice".to_owned(),
+                },
+                &alice,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let game = TestGame {
+            id: response.id,
+            token: response.owner_token,
+        };
+        draw(&service, &game).await;
+        draw(&service, &game).await;
+        // Anonymous games are not recorded
+        let anonymous = create_game(&service).await;
+        for game in [&game, &anonymous] {
+            service
+                .destroy_game(authorized(
+                    solitaire_grpc::proto::DestroyGameRequest {
+                        id: game.id.clone(),
+                    },
+                    &game.token,
+                ))
+                .await
+                .unwrap();
+        }
+
+        let stats = service
+            .get_player_stats(tonic::Request::new(
+                solitaire_grpc::proto::GetPlayerStatsRequest {
+                    player: "alice".to_owned(),
+                    variant: None,
+                    period: solitaire_grpc::proto::Period::Day.into(),
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .stats
+            .unwrap();
+        assert_eq!((stats.played, stats.won, stats.moves), (1, 0, 2));
+        let entries = service
+            .get_leaderboard(tonic::Request::new(
+                solitaire_grpc::proto::GetLeaderboardRequest {
+                    variant: Some(solitaire_grpc::proto::Variant::Thoughtful.into()),
+                    period: solitaire_grpc::proto::Period::AllTime.into(),
+                    limit: 0,
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .entries;
+        assert!(entries.is_empty());
+        let entries = service
+            .get_leaderboard(tonic::Request::new(
+                solitaire_grpc::proto::GetLeaderboardRequest {
+                    variant: None,
+                    period: solitaire_grpc::proto::Period::AllTime.into(),
+                    limit: 0,
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .entries;
+        assert_eq!(entries, [stats]);
+    }
+
+    #[tokio::test]
+    async fn won_deals_are_revealed() {
+        let date = FixedDate(Arc::new(std::sync::Mutex::new(
+            NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+        )));
+        let service = new_service_on(Box::new(date.clone()));
+        let alice = register(&service, "alice").await;
+        let response = service
+            .create_daily_game(as_player(
+                solitaire_grpc::proto::CreateDailyGameRequest {
+                    player: "alice".to_owned(),
+                },
+                &alice,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let game = TestGame {
+            id: response.id,
+            token: response.owner_token,
+        };
+        let reveal = |token: &str| {
+            service.reveal_deal(authorized(
+                solitaire_grpc::proto::RevealDealRequest {
+                    id: game.id.clone(),
+                },
+                token,
+            ))
+        };
+
+        let err = reveal(&game.token).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+        // Winning a whole game is out of the scope of this test
+        let handle = service
+            .games
+            .find_game(&try_parse_id(&game.id).unwrap())
+            .await
+            .unwrap();
+        handle.lock().await.victory = true;
+        let err = reveal(&game.token).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+        *date.0.lock().unwrap() = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let err = reveal("not-the-owner").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let deal = reveal(&game.token).await.unwrap().into_inner();
+        let seed = deal.seed.unwrap();
+        let deck: Vec<solitaire_grpc::proto::Card> = shuffled_deck(&mut XorShifEngine::new(seed))
+            .iter()
+            .map(|card| card.into())
+            .collect();
+        assert_eq!(deal.deck, deck);
+    }
+
+    #[tokio::test]
+    async fn lost_deals_are_revealed() {
+        let service = new_service();
+        let game = create_game(&service).await;
+        draw(&service, &game).await;
+        let reveal = |token: &str| {
+            service.reveal_deal(authorized(
+                solitaire_grpc::proto::RevealDealRequest {
+                    id: game.id.clone(),
+                },
+                token,
+            ))
+        };
+        let err = reveal(&game.token).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+        // Destroying a game ends it without winning it
+        service
+            .destroy_game(authorized(
+                solitaire_grpc::proto::DestroyGameRequest {
+                    id: game.id.clone(),
+                },
+                &game.token,
+            ))
+            .await
+            .unwrap();
+        let err = reveal("not-the-owner").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let deal = reveal(&game.token).await.unwrap().into_inner();
+        assert_eq!(deal.deck.len(), 52);
+
+        let err = service
+            .reveal_deal(authorized(
+                solitaire_grpc::proto::RevealDealRequest {
+                    id: Uuid::new_v4().to_string(),
+                },
+                &game.token,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn daily_games_share_their_deal() {
+        let date = FixedDate(Arc::new(std::sync::Mutex::new(
+            NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+        )));
+        let service = new_service_on(Box::new(date.clone()));
+        let tokens = HashMap::from([
+            ("alice", register(&service, "alice").await),
+            ("bob", register(&service, "bob").await),
+        ]);
+        let create = |player: &str| {
+            service.create_daily_game(as_player(
+                solitaire_grpc::proto::CreateDailyGameRequest {
+                    player: player.to_owned(),
+                },
+                &tokens[player],
+            ))
+        };
+
+        let alice = create("alice").await.unwrap().into_inner();
+        let bob = create("bob").await.unwrap().into_inner();
+        assert_eq!(alice.state, bob.state);
+        assert_eq!(alice.config, bob.config);
+        assert_eq!(alice.config.as_ref().unwrap().seed, None);
+        let first_deal = alice.state.clone();
+        let err = create("alice").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::AlreadyExists);
+
+        let alice = TestGame {
+            id: alice.id,
+            token: alice.owner_token,
+        };
+        let bob = TestGame {
+            id: bob.id,
+            token: bob.owner_token,
+        };
+        draw(&service, &alice).await;
+        for game in [&alice, &bob] {
+            service
+                .destroy_game(authorized(
+                    solitaire_grpc::proto::DestroyGameRequest {
+                        id: game.id.clone(),
+                    },
+                    &game.token,
+                ))
+                .await
+                .unwrap();
+        }
+        let daily_results = |date: Option<&str>| {
+            service.get_daily_results(tonic::Request::new(
+                solitaire_grpc::proto::GetDailyResultsRequest {
+                    date: date.map(|d| d.to_owned()),
+                },
+            ))
+        };
+        let response = daily_results(None).await.unwrap().into_inner();
+        assert_eq!(response.date, "2024-02-29");
+        let ranking: Vec<_> = response
+            .results
+            .iter()
+            .map(|r| (r.player.as_str(), r.moves))
+            .collect();
+        assert_eq!(ranking, [("bob", 0), ("alice", 1)]);
+        let err = daily_results(Some("29/02/2024")).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        *date.0.lock().unwrap() = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let next = create("alice").await.unwrap().into_inner();
+        assert_ne!(next.state, first_deal);
+        assert!(daily_results(None)
+            .await
+            .unwrap()
+            .into_inner()
+            .results
+            .is_empty());
+        let response = daily_results(Some("2024-02-29"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.results.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn daily_deals_cannot_be_dealt_as_custom_games() {
+        let today = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        let service = new_service_on(Box::new(FixedDate(Arc::new(std::sync::Mutex::new(today)))));
+        let alice = register(&service, "alice").await;
+        let daily = service
+            .create_daily_game(as_player(
+                solitaire_grpc::proto::CreateDailyGameRequest {
+                    player: "alice".to_owned(),
+                },
+                &alice,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let custom = |options: GameOptions| {
+            service.create_game(tonic::Request::new(
+                solitaire_grpc::proto::CreateGameRequest {
+                    config: Some((&options).into()),
+                    require_spectator_token: false,
+                    player: String::new(),
+                },
+            ))
+        };
+        let tableaus =
+            |response: solitaire_grpc::proto::CreateGameResponse| response.state.unwrap().tableaus;
+
+        // Without the secret of the server, the seed of the day can only be guessed
+        for secret in [&b""[..], b"guess"] {
+            let options = DailySecret::new(secret).daily_options(today);
+            let guess = custom(options).await.unwrap().into_inner();
+            assert_ne!(tableaus(guess), tableaus(daily.clone()));
+        }
+        let options = DailySecret::new(b"test").daily_options(today);
+        let known = custom(options).await.unwrap().into_inner();
+        assert_eq!(tableaus(known), tableaus(daily));
+    }
+
+    #[tokio::test]
+    async fn players_cannot_be_impersonated() {
+        let date = FixedDate(Arc::new(std::sync::Mutex::new(
+            NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+        )));
+        let service = new_service_on(Box::new(date));
+        let alice = register(&service, "alice").await;
+        let bob = register(&service, "bob").await;
+        let err = service
+            .register_player(tonic::Request::new(
+                solitaire_grpc::proto::RegisterPlayerRequest {
+                    player: "alice".to_owned(),
+                },
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::AlreadyExists);
+
+        let create = |player: &str, token: Option<&str>| {
+            let message = solitaire_grpc::proto::CreateGameRequest {
+                config: None,
+                require_spectator_token: false,
+                player: player.to_owned(),
+            };
+            service.create_game(match token {
+                None => tonic::Request::new(message),
+                Some(token) => as_player(message, token),
+            })
+        };
+        let err = create("alice", None).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        let err = create("alice", Some(&bob)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = create("carol", Some(&bob)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+        create("alice", Some(&alice)).await.unwrap();
+
+        // Bob may not take the daily game of alice, which is still hers to play
+        let daily = |player: &str, token: &str| {
+            service.create_daily_game(as_player(
+                solitaire_grpc::proto::CreateDailyGameRequest {
+                    player: player.to_owned(),
+                },
+                token,
+            ))
+        };
+        let err = daily("alice", &bob).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        daily("alice", &alice).await.unwrap();
+        daily("bob", &bob).await.unwrap();
+
+        // Nor race in her name
+        let created = service
+            .create_match(as_player(
+                solitaire_grpc::proto::CreateMatchRequest {
+                    config: None,
+                    player: "bob".to_owned(),
+                },
+                &bob,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let err = service
+            .join_match(as_player(
+                solitaire_grpc::proto::JoinMatchRequest {
+                    match_id: created.match_id,
+                    player: "alice".to_owned(),
+                },
+                &bob,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn match_players_race_on_the_same_deal() {
+        let service = new_service();
+        let alice = register(&service, "alice").await;
+        let bob = register(&service, "bob").await;
+        let created = service
+            .create_match(as_player(
+                solitaire_grpc::proto::CreateMatchRequest {
+                    config: None,
+                    player: "alice".to_owned(),
+                },
+                &alice,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let join = |player: &str| {
+            service.join_match(as_player(
+                solitaire_grpc::proto::JoinMatchRequest {
+                    match_id: created.match_id.clone(),
+                    player: player.to_owned(),
+                },
+                &bob,
+            ))
+        };
+        let alice = created.game.unwrap();
+        let bob = join("bob").await.unwrap().into_inner().game.unwrap();
+        assert_eq!(alice.state, bob.state);
+        assert_ne!(alice.id, bob.id);
+        let err = join("bob").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::AlreadyExists);
+
+        let mut watch = service
+            .watch_match(tonic::Request::new(
+                solitaire_grpc::proto::WatchMatchRequest {
+                    match_id: created.match_id.clone(),
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let progress = |response: solitaire_grpc::proto::WatchMatchResponse| -> Vec<_> {
+            response
+                .racers
+                .into_iter()
+                .map(|r| (r.player, r.moves))
+                .collect()
+        };
+        let snapshot = watch.next().await.unwrap().unwrap();
+        assert_eq!(
+            progress(snapshot),
+            [("alice".to_owned(), 0), ("bob".to_owned(), 0)]
+        );
+
+        let bob = TestGame {
+            id: bob.id,
+            token: bob.owner_token,
+        };
+        draw(&service, &bob).await;
+        let update = watch.next().await.unwrap().unwrap();
+        assert_eq!(update.winner, None);
+        assert_eq!(
+            progress(update),
+            [("alice".to_owned(), 0), ("bob".to_owned(), 1)]
+        );
+
+        let err = service
+            .watch_match(tonic::Request::new(
+                solitaire_grpc::proto::WatchMatchRequest {
+                    match_id: Uuid::new_v4().to_string(),
+                },
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn lagging_watcher_is_resynchronized() {
+        let service = new_service();
+        let game = create_game(&service).await;
+        let mut stream = service
+            .watch(tonic::Request::new(solitaire_grpc::proto::WatchRequest {
+                id: game.id.clone(),
+                from_sequence: None,
+                deltas: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        for _ in 0..(DEFAULT_WATCH_CHANNEL_SIZE * 4) {
+            draw(&service, &game).await;
+        }
+        let current = service
+            .get_game(tonic::Request::new(solitaire_grpc::proto::GetGameRequest {
+                id: game.id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .state;
+
+        // Whatever was skipped, the watcher must end up on the current state
+        let mut last = None;
+        while let Ok(Some(msg)) =
+            tokio::time::timeout(Duration::from_millis(100), stream.next()).await
+        {
+            last = msg.unwrap().state;
+        }
+        assert_eq!(last, current);
+    }
+
+    #[tokio::test]
+    async fn watch_resumes_from_sequence() {
+        let service = new_service();
+        let game = create_game(&service).await;
+        let watch = |from_sequence| {
+            service.watch(tonic::Request::new(solitaire_grpc::proto::WatchRequest {
+                id: game.id.clone(),
+                from_sequence,
+                deltas: false,
+            }))
+        };
+
+        for _ in 0..5 {
+            draw(&service, &game).await;
+        }
+        let mut stream = watch(Some(3)).await.unwrap().into_inner();
+        draw(&service, &game).await;
+        for sequence in 3..=6 {
+            let response = stream.next().await.unwrap().unwrap();
+            assert_eq!(response.sequence, sequence);
+            assert!(response.action.is_some());
+        }
+
+        assert!(watch(Some(7)).await.is_ok());
+        let err = watch(Some(8)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        for _ in 0..HISTORY_SIZE {
+            draw(&service, &game).await;
+        }
+        let err = watch(Some(1)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::OutOfRange);
+        let oldest = 7;
+        let response = watch(Some(oldest))
+            .await
+            .unwrap()
+            .into_inner()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.sequence, oldest);
+    }
+
+    #[tokio::test]
+    async fn watchers_may_ask_for_deltas() {
+        let service = new_service();
+        let game = create_game(&service).await;
+        let mut stream = service
+            .watch(tonic::Request::new(solitaire_grpc::proto::WatchRequest {
+                id: game.id.clone(),
+                from_sequence: None,
+                deltas: true,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let count = STATE_INTERVAL as usize + 2;
+        for _ in 0..count {
+            draw(&service, &game).await;
+        }
+
+        let mut synced = solitaire_grpc::SyncedState::default();
+        let mut states = 0;
+        for _ in 0..=count {
+            let update = stream.next().await.unwrap().unwrap();
+            assert_ne!(update.state.is_some(), update.delta.is_some());
+            states += update.state.is_some() as usize;
+            assert_eq!(synced.apply(&update), Ok(true));
+        }
+        // The first update and one after the interval carry the full state
+        assert_eq!(states, 2);
+        let current = service
+            .get_game(tonic::Request::new(solitaire_grpc::proto::GetGameRequest {
+                id: game.id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .state;
+        assert_eq!(synced.state(), current.as_ref());
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_games() {
+        let service = new_service();
+        let game = create_game(&service).await;
+        draw(&service, &game).await;
+        let mut stream = service
+            .watch(tonic::Request::new(solitaire_grpc::proto::WatchRequest {
+                id: game.id.clone(),
+                from_sequence: None,
+                deltas: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stream.next().await.unwrap().unwrap().version, 1);
+
+        let snapshot = MemoryStorage::<SolitaireRules>::default();
+        service.shut_down(Some(&snapshot)).await;
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert!(stream.next().await.is_none());
+
+        let records = snapshot.load_all().await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0.to_string(), game.id);
+        assert_eq!(records[0].1.actions, [solitaire_backend::Action::Draw]);
+
+        let err = service
+            .act(authorized(draw_request(&game), &game.token))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+        let err = service
+            .create_game(tonic::Request::new(
+                solitaire_grpc::proto::CreateGameRequest::default(),
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn clients_are_rate_limited() {
+        let service = SolitaireService::new(
+            ServiceLimits {
+                create_rate: RateLimit {
+                    per_second: 0.1,
+                    burst: 2,
+                },
+                max_watchers: 1,
+                ..ServiceLimits::default()
+            },
+            Box::new(MemoryStorage::default()),
+            Arc::default(),
+            Box::new(daily::SystemDateSource),
+            DailySecret::new(b"test"),
+        );
+        let from = |client: &str| {
+            let mut request =
+                tonic::Request::new(solitaire_grpc::proto::CreateGameRequest::default());
+            let client = ClientAddr(format!("{client}:1234").parse().unwrap());
+            request.extensions_mut().insert(client);
+            request
+        };
+
+        for _ in 0..2 {
+            service.create_game(from("192.0.2.1")).await.unwrap();
+        }
+        let err = service.create_game(from("192.0.2.1")).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        let delay = solitaire_grpc::retry_delay(&err).unwrap();
+        assert!(delay > Duration::from_secs(9) && delay <= Duration::from_secs(10));
+        service.create_game(from("192.0.2.2")).await.unwrap();
+
+        // Calls made in the server itself are not limited
+        let game = create_game(&service).await;
+        let watch = || {
+            service.watch(tonic::Request::new(solitaire_grpc::proto::WatchRequest {
+                id: game.id.clone(),
+                from_sequence: None,
+                deltas: false,
+            }))
+        };
+        let _stream = watch().await.unwrap();
+        let err = watch().await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        assert!(solitaire_grpc::retry_delay(&err).is_some());
+    }
+
+    /// A request stream fed by the test, each message framed as a client sends it.
+    struct PlayRequests(hyper::body::Sender);
+
+    impl PlayRequests {
+        async fn open(id: &str) -> (Self, tonic::Streaming<solitaire_grpc::proto::PlayRequest>) {
+            use tonic::codec::Codec;
+            let (sender, body) = hyper::Body::channel();
+            let mut codec = tonic::codec::ProstCodec::<(), _>::default();
+            let requests = tonic::Streaming::new_request(codec.decoder(), body);
+            let mut stream = Self(sender);
+            stream
+                .send(play_request::Request::Open(play_request::Open {
+                    id: id.to_owned(),
+                    from_sequence: None,
+                    deltas: false,
+                }))
+                .await;
+            (stream, requests)
+        }
+
+        async fn send(&mut self, request: play_request::Request) {
+            let message = prost::Message::encode_to_vec(&solitaire_grpc::proto::PlayRequest {
+                request: Some(request),
+            });
+            let mut frame = vec![0];
+            frame.extend((message.len() as u32).to_be_bytes());
+            frame.extend(message);
+            self.0.send_data(frame.into()).await.unwrap();
+        }
+
+        async fn play(&mut self, request_id: u64, expected_version: u64) {
+            self.send(play_request::Request::Move(play_request::Move {
+                request_id,
+                action: Some(solitaire_backend::Action::Draw.into()),
+                expected_version: Some(expected_version),
+                idempotency_key: String::new(),
+            }))
+            .await;
+        }
+    }
+
+    async fn next_event(
+        responses: &mut ReceiverStream<PlayMessage>,
+    ) -> Result<play_response::Event, tonic::Status> {
+        tokio::time::timeout(TEST_TIMEOUT, responses.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .map(|response| response.event.unwrap())
+    }
+
+    #[tokio::test]
+    async fn moves_are_played_in_sessions() {
+        let service = new_service();
+        let game = create_game(&service).await;
+
+        let (_requests, stream) = PlayRequests::open(&game.id).await;
+        let err = service.play(tonic::Request::new(stream)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+        let (mut requests, stream) = PlayRequests::open(&game.id).await;
+        let mut responses = service
+            .play(authorized(stream, &game.token))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(matches!(
+            next_event(&mut responses).await.unwrap(),
+            play_response::Event::Update(update) if update.version == 0
+        ));
+
+        requests.play(7, 0).await;
+        requests.play(8, 0).await;
+        let mut results = Vec::new();
+        let mut versions = Vec::new();
+        while results.len() < 2 {
+            match next_event(&mut responses).await.unwrap() {
+                play_response::Event::Update(update) => versions.push(update.version),
+                play_response::Event::Result(result) => results.push(result),
+            }
+        }
+        assert_eq!(results[0].request_id, 7);
+        assert!(matches!(
+            &results[0].outcome,
+            Some(move_result::Outcome::Played(response)) if response.version == 1
+        ));
+        // A move refused by the game leaves the session open
+        assert_eq!(results[1].request_id, 8);
+        assert!(matches!(
+            &results[1].outcome,
+            Some(move_result::Outcome::Refused(refused))
+                if refused.code == tonic::Code::Aborted as i32
+        ));
+
+        requests
+            .send(play_request::Request::Snapshot(play_request::Snapshot {}))
+            .await;
+        loop {
+            match next_event(&mut responses).await.unwrap() {
+                play_response::Event::Update(update) if update.action.is_none() => {
+                    assert_eq!(update.version, 1);
+                    assert!(update.state.is_some());
+                    break;
+                }
+                play_response::Event::Update(update) => versions.push(update.version),
+                play_response::Event::Result(_) => panic!("Unexpected result"),
+            }
+        }
+
+        service
+            .destroy_game(authorized(
+                solitaire_grpc::proto::DestroyGameRequest {
+                    id: game.id.clone(),
+                },
+                &game.token,
+            ))
+            .await
+            .unwrap();
+        loop {
+            match next_event(&mut responses).await {
+                Ok(play_response::Event::Update(update)) => versions.push(update.version),
+                Ok(play_response::Event::Result(_)) => panic!("Unexpected result"),
+                Err(status) => {
+                    assert_eq!(status.code(), tonic::Code::NotFound);
+                    break;
+                }
+            }
+        }
+        assert_eq!(versions, [1]);
+    }
+}
@@ -0,0 +1,304 @@
+use crate::race::Race;
+use crate::registry::{
+    ActiveGame, GameRules, Outcome, Played, SessionEvent, SessionRequest, Update, Watch,
+};
+use crate::storage::{from_millis, invalid_data, to_millis, FileFormat};
+use boards::cards::FrenchDeck;
+use chrono::NaiveDate;
+use solitaire_backend::{
+    Action, ActionResult, Card, Game, GameOptions, MemoryGame, ParseActionError, Variant, DECK_SIZE,
+};
+use solitaire_grpc::proto::play_request;
+use solitaire_grpc::proto::play_response::{self, move_result};
+use std::collections::HashSet;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How a solitaire game is dealt: the deck and the rules it is played with, and the day it is the
+/// daily game of, if it is one.
+#[derive(Clone)]
+pub struct Deal {
+    pub deck: FrenchDeck,
+    pub options: GameOptions,
+    pub daily: Option<NaiveDate>,
+}
+
+/// Outcome of a finished game, kept for player statistics.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameResult {
+    pub player: String,
+    pub variant: Variant,
+    pub won: bool,
+    pub moves: u32,
+    pub duration: Duration,
+    pub score: Option<i32>,
+    pub finished_at: SystemTime,
+    pub daily: Option<NaiveDate>,
+}
+
+/// The games of the `Solitaire` service.
+pub struct SolitaireRules;
+
+#[tonic::async_trait]
+impl GameRules for SolitaireRules {
+    type Setup = Deal;
+    type Game = MemoryGame;
+    type Action = Action;
+    /// The match the game is played in, progress is reported to it after every move.
+    type Attachment = Option<Arc<Race>>;
+    type Result = GameResult;
+    type ProtoAction = solitaire_grpc::proto::Action;
+    type State = solitaire_grpc::proto::State;
+    type Delta = solitaire_grpc::proto::StateDelta;
+    type WatchResponse = solitaire_grpc::proto::WatchResponse;
+    type PlayRequest = solitaire_grpc::proto::PlayRequest;
+    type PlayResponse = solitaire_grpc::proto::PlayResponse;
+
+    fn deal(deal: &Deal) -> MemoryGame {
+        MemoryGame::with_options(deal.deck.clone(), deal.options)
+    }
+
+    async fn act(game: &mut MemoryGame, action: Action) -> Outcome {
+        match game.act(action).await {
+            ActionResult::Failed(s) => Outcome::Refused(s),
+            ActionResult::Victory => Outcome::Won,
+            ActionResult::OnGoing => Outcome::Played,
+        }
+    }
+
+    fn action(action: &solitaire_grpc::proto::Action) -> Result<Action, tonic::Status> {
+        action.try_into()
+    }
+
+    fn view(game: &MemoryGame) -> solitaire_grpc::proto::State {
+        (&game.player_view()).into()
+    }
+
+    fn delta(
+        from: &solitaire_grpc::proto::State,
+        to: &solitaire_grpc::proto::State,
+    ) -> solitaire_grpc::proto::StateDelta {
+        solitaire_grpc::state_delta(from, to)
+    }
+
+    fn checksum(state: &solitaire_grpc::proto::State) -> u64 {
+        solitaire_grpc::state_checksum(state)
+    }
+
+    fn watch_response(update: Update<Self>) -> solitaire_grpc::proto::WatchResponse {
+        solitaire_grpc::proto::WatchResponse {
+            action: update.action,
+            state: update.state,
+            version: update.version,
+            sequence: update.sequence,
+            delta: update.delta,
+            checksum: Some(update.checksum),
+        }
+    }
+
+    fn session_request(request: solitaire_grpc::proto::PlayRequest) -> SessionRequest<Self> {
+        match request.request {
+            None => SessionRequest::Empty,
+            Some(play_request::Request::Open(open)) => SessionRequest::Open(Watch {
+                id: open.id,
+                from_sequence: open.from_sequence,
+                deltas: open.deltas,
+            }),
+            Some(play_request::Request::Move(request)) => SessionRequest::Move {
+                request_id: request.request_id,
+                action: request.action,
+                expected_version: request.expected_version,
+                idempotency_key: request.idempotency_key,
+            },
+            Some(play_request::Request::Snapshot(_)) => SessionRequest::Snapshot,
+        }
+    }
+
+    fn session_event(event: SessionEvent<Self>) -> solitaire_grpc::proto::PlayResponse {
+        let event = match event {
+            SessionEvent::Update(update) => play_response::Event::Update(update),
+            SessionEvent::Result {
+                request_id,
+                outcome,
+            } => play_response::Event::Result(play_response::MoveResult {
+                request_id,
+                outcome: Some(match outcome {
+                    Ok(played) => move_result::Outcome::Played(played.into()),
+                    Err(status) => move_result::Outcome::Refused(play_response::Refused {
+                        code: status.code() as i32,
+                        message: status.message().to_owned(),
+                    }),
+                }),
+            }),
+        };
+        solitaire_grpc::proto::PlayResponse { event: Some(event) }
+    }
+
+    fn result(game: &ActiveGame<Self>) -> Option<GameResult> {
+        let finished_at = SystemTime::now();
+        game.player.as_ref().map(|player| GameResult {
+            player: player.clone(),
+            variant: game.state.options().variant,
+            won: game.victory,
+            moves: game.moves,
+            duration: finished_at
+                .duration_since(game.created_at)
+                .unwrap_or_default(),
+            score: game.state.score(),
+            finished_at,
+            daily: game.setup.daily,
+        })
+    }
+
+    /// Tells the match of the game, if any, where its player stands.
+    fn played(game: &ActiveGame<Self>) {
+        if let (Some(race), Some(player)) = (&game.attachment, &game.player) {
+            let foundation_cards = game
+                .state
+                .foundations()
+                .iter()
+                .map(|f| f.value as u32)
+                .sum();
+            race.report(player, foundation_cards, game.moves, game.victory);
+        }
+    }
+}
+
+impl From<Played<SolitaireRules>> for solitaire_grpc::proto::ActResponse {
+    fn from(played: Played<SolitaireRules>) -> Self {
+        Self {
+            victory: played.victory,
+            state: Some(played.state),
+            version: played.version,
+        }
+    }
+}
+
+/// The deal of a game is written as its options in the header, then its deck on a line.
+impl FileFormat for SolitaireRules {
+    const GAME_EXTENSION: &'static str = "game";
+    const RESULTS_FILE: &'static str = "results.log";
+
+    fn format_setup(deal: &Deal) -> (Vec<String>, String) {
+        let options = &deal.options;
+        let mut tokens = vec![
+            format!("variant={}", options.variant),
+            format!("draw={}", options.draw_count),
+            format!("scoring={}", options.scoring),
+        ];
+        if let Some(limit) = options.redeal_limit {
+            tokens.push(format!("redeals={limit}"));
+        }
+        if let Some(seed) = options.seed {
+            tokens.push(format!("seed={seed}"));
+        }
+        if options.auto_foundation {
+            tokens.push("auto-foundation".to_owned());
+        }
+        if let Some(daily) = deal.daily {
+            tokens.push(format!("daily={daily}"));
+        }
+        let deck = deal
+            .deck
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        (tokens, deck)
+    }
+
+    fn parse_setup(tokens: Vec<&str>, line: &str) -> io::Result<Deal> {
+        fn invalid<T>(token: &str) -> impl FnOnce(T) -> io::Error + '_ {
+            move |_| invalid_data(format!("Invalid option {token}"))
+        }
+
+        let mut options = GameOptions::default();
+        let mut daily = None;
+        for token in tokens {
+            match token.split_once('=') {
+                Some(("daily", v)) => daily = Some(v.parse().map_err(invalid(token))?),
+                Some(("variant", v)) => options.variant = v.parse().map_err(invalid(token))?,
+                Some(("draw", v)) => options.draw_count = v.parse().map_err(invalid(token))?,
+                Some(("scoring", v)) => options.scoring = v.parse().map_err(invalid(token))?,
+                Some(("redeals", v)) => {
+                    options.redeal_limit = Some(v.parse().map_err(invalid(token))?)
+                }
+                Some(("seed", v)) => options.seed = Some(v.parse().map_err(invalid(token))?),
+                None if token == "auto-foundation" => options.auto_foundation = true,
+                _ => return Err(invalid_data(format!("Unknown option {token}"))),
+            }
+        }
+        // Stored games are checked as new ones are, they are dealt the same way
+        let options = GameOptions::try_from(&solitaire_grpc::proto::GameConfig::from(&options))
+            .map_err(|status| invalid_data(status.message().to_owned()))?;
+        let deck: FrenchDeck = line
+            .split_whitespace()
+            .map(|c| Card::from_str(c).map_err(|e| invalid_data(e.to_string())))
+            .collect::<io::Result<_>>()?;
+        let distinct: HashSet<_> = deck.iter().map(|card| card.to_string()).collect();
+        if deck.len() != DECK_SIZE || distinct.len() != DECK_SIZE {
+            return Err(invalid_data(format!(
+                "The deck is not made of {DECK_SIZE} different cards"
+            )));
+        }
+        Ok(Deal {
+            deck,
+            options,
+            daily,
+        })
+    }
+
+    fn parse_action(line: &str) -> io::Result<Action> {
+        Action::from_str(line).map_err(|ParseActionError::Invalid(e)| invalid_data(e))
+    }
+
+    fn format_result(result: &GameResult) -> String {
+        format!(
+            "{} {} {} {} {} {} {} {}",
+            to_millis(result.finished_at),
+            result.player,
+            result.variant,
+            if result.won { "won" } else { "lost" },
+            result.moves,
+            result.duration.as_millis(),
+            result
+                .score
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "-".to_owned()),
+            result
+                .daily
+                .map(|d| d.to_string())
+                .unwrap_or_else(|| "-".to_owned())
+        )
+    }
+
+    fn parse_result(line: &str) -> io::Result<GameResult> {
+        let invalid = || invalid_data(format!("Invalid result {line}"));
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.len() != 8 {
+            return Err(invalid());
+        }
+        Ok(GameResult {
+            finished_at: from_millis(fields[0].parse().map_err(|_| invalid())?),
+            player: fields[1].to_owned(),
+            variant: fields[2].parse().map_err(|_| invalid())?,
+            won: match fields[3] {
+                "won" => true,
+                "lost" => false,
+                _ => return Err(invalid()),
+            },
+            moves: fields[4].parse().map_err(|_| invalid())?,
+            duration: Duration::from_millis(fields[5].parse().map_err(|_| invalid())?),
+            score: match fields[6] {
+                "-" => None,
+                s => Some(s.parse().map_err(|_| invalid())?),
+            },
+            daily: match fields[7] {
+                "-" => None,
+                d => Some(d.parse().map_err(|_| invalid())?),
+            },
+        })
+    }
+}
@@ -0,0 +1,213 @@
+use crate::solitaire::GameResult;
+use solitaire_backend::Variant;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+const DEFAULT_LEADERBOARD_SIZE: usize = 10;
+const MAX_LEADERBOARD_SIZE: usize = 100;
+const MAX_PLAYER_LEN: usize = 32;
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Player names end up in storage headers, so they are kept to a small set of characters.
+pub fn validate_player(player: &str) -> Result<(), tonic::Status> {
+    if player.is_empty() || player.len() > MAX_PLAYER_LEN {
+        Err(tonic::Status::invalid_argument(format!(
+            "Player names must be 1 to {MAX_PLAYER_LEN} characters long"
+        )))
+    } else if !player
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        Err(tonic::Status::invalid_argument(
+            "Player names may only contain letters, digits, '_', '-' and '.'",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Which results count towards statistics.
+pub struct ResultFilter {
+    pub variant: Option<Variant>,
+    pub since: Option<SystemTime>,
+}
+
+impl ResultFilter {
+    pub fn from_proto(
+        variant: Option<i32>,
+        period: i32,
+        now: SystemTime,
+    ) -> Result<Self, tonic::Status> {
+        use solitaire_grpc::proto::Period;
+        let variant = match variant {
+            None => None,
+            Some(v) => Some(
+                solitaire_grpc::variant_from_proto(v)
+                    .ok_or_else(|| tonic::Status::invalid_argument("Invalid `variant`"))?,
+            ),
+        };
+        let days = match Period::from_i32(period) {
+            None => return Err(tonic::Status::invalid_argument("Invalid `period`")),
+            Some(Period::AllTime) => None,
+            Some(Period::Day) => Some(1),
+            Some(Period::Week) => Some(7),
+            Some(Period::Month) => Some(30),
+        };
+        Ok(Self {
+            variant,
+            since: days.and_then(|d| now.checked_sub(DAY * d)),
+        })
+    }
+
+    fn matches(&self, result: &GameResult) -> bool {
+        self.variant.iter().all(|v| *v == result.variant)
+            && self.since.iter().all(|since| result.finished_at >= *since)
+    }
+}
+
+fn add_result(stats: &mut solitaire_grpc::proto::PlayerStats, result: &GameResult) {
+    stats.played += 1;
+    stats.moves += result.moves as u64;
+    if let Some(score) = result.score {
+        stats.best_score = Some(stats.best_score.map_or(score, |best| best.max(score)));
+    }
+    if result.won {
+        stats.won += 1;
+        let duration = result.duration.as_millis() as u64;
+        stats.fastest_win_ms = Some(stats.fastest_win_ms.map_or(duration, |f| f.min(duration)));
+        stats.fewest_moves = Some(
+            stats
+                .fewest_moves
+                .map_or(result.moves, |f| f.min(result.moves)),
+        );
+    }
+}
+
+pub fn player_stats(
+    results: &[GameResult],
+    player: &str,
+    filter: &ResultFilter,
+) -> solitaire_grpc::proto::PlayerStats {
+    let mut stats = solitaire_grpc::proto::PlayerStats {
+        player: player.to_owned(),
+        ..Default::default()
+    };
+    for result in results
+        .iter()
+        .filter(|r| r.player == player && filter.matches(r))
+    {
+        add_result(&mut stats, result);
+    }
+    stats
+}
+
+/// Ranks players by wins, then best score, then fastest win, and finally by name so the order is
+/// stable.
+pub fn leaderboard(
+    results: &[GameResult],
+    filter: &ResultFilter,
+    limit: u32,
+) -> Vec<solitaire_grpc::proto::PlayerStats> {
+    let mut players: HashMap<&str, solitaire_grpc::proto::PlayerStats> = HashMap::new();
+    for result in results.iter().filter(|r| filter.matches(r)) {
+        let stats = players.entry(result.player.as_str()).or_insert_with(|| {
+            solitaire_grpc::proto::PlayerStats {
+                player: result.player.clone(),
+                ..Default::default()
+            }
+        });
+        add_result(stats, result);
+    }
+    let mut entries: Vec<_> = players.into_values().collect();
+    entries.sort_by(|a, b| {
+        b.won
+            .cmp(&a.won)
+            .then(b.best_score.cmp(&a.best_score))
+            .then(
+                a.fastest_win_ms
+                    .unwrap_or(u64::MAX)
+                    .cmp(&b.fastest_win_ms.unwrap_or(u64::MAX)),
+            )
+            .then(a.player.cmp(&b.player))
+    });
+    let limit = match limit as usize {
+        0 => DEFAULT_LEADERBOARD_SIZE,
+        l => l.min(MAX_LEADERBOARD_SIZE),
+    };
+    entries.truncate(limit);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn result(
+        player: &str,
+        won: bool,
+        score: i32,
+        duration_secs: u64,
+        age_days: u32,
+    ) -> GameResult {
+        let now = UNIX_EPOCH + DAY * 100;
+        GameResult {
+            player: player.to_owned(),
+            variant: Variant::Klondike,
+            won,
+            moves: 100,
+            duration: Duration::from_secs(duration_secs),
+            score: Some(score),
+            finished_at: now - DAY * age_days,
+            daily: None,
+        }
+    }
+
+    #[test]
+    fn leaderboard_ranks_players() {
+        let now = UNIX_EPOCH + DAY * 100;
+        let results = [
+            result("alice", true, 500, 300, 0),
+            result("alice", false, 100, 60, 0),
+            result("bob", true, 600, 200, 3),
+            result("bob", true, 400, 250, 20),
+            result("carol", true, 500, 200, 0),
+            result("dave", false, 900, 10, 0),
+        ];
+        let all_time = ResultFilter::from_proto(None, 0, now).unwrap();
+        let ranking: Vec<_> = leaderboard(&results, &all_time, 0)
+            .into_iter()
+            .map(|s| (s.player, s.played, s.won))
+            .collect();
+        assert_eq!(
+            ranking,
+            [
+                ("bob".to_owned(), 2, 2),
+                ("carol".to_owned(), 1, 1),
+                ("alice".to_owned(), 2, 1),
+                ("dave".to_owned(), 1, 0),
+            ]
+        );
+
+        let week = ResultFilter::from_proto(None, solitaire_grpc::proto::Period::Week.into(), now)
+            .unwrap();
+        let ranking: Vec<_> = leaderboard(&results, &week, 2)
+            .into_iter()
+            .map(|s| s.player)
+            .collect();
+        assert_eq!(ranking, ["bob", "carol"]);
+
+        let bob = player_stats(&results, "bob", &all_time);
+        assert_eq!(bob.best_score, Some(600));
+        assert_eq!(bob.fastest_win_ms, Some(200_000));
+        assert_eq!(bob.moves, 200);
+    }
+
+    #[test]
+    fn player_names_are_validated() {
+        assert!(validate_player("alice.b-2_c").is_ok());
+        assert!(validate_player("").is_err());
+        assert!(validate_player("with space").is_err());
+        assert!(validate_player(&"x".repeat(MAX_PLAYER_LEN + 1)).is_err());
+    }
+}
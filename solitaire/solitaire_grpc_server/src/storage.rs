@@ -0,0 +1,501 @@
+use crate::auth::{GameTokens, TokenHash};
+use crate::registry::GameRules;
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// Everything needed to rebuild a game: how it was dealt, who may access it and the moves played
+/// on it.
+pub struct GameRecord<R: GameRules> {
+    pub setup: R::Setup,
+    pub tokens: GameTokens,
+    /// Player the result of the game is recorded for, if any.
+    pub player: Option<String>,
+    pub created_at: SystemTime,
+    pub actions: Vec<R::Action>,
+}
+
+impl<R: GameRules> Clone for GameRecord<R> {
+    fn clone(&self) -> Self {
+        Self {
+            setup: self.setup.clone(),
+            tokens: self.tokens.clone(),
+            player: self.player.clone(),
+            created_at: self.created_at,
+            actions: self.actions.clone(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+pub trait GameStorage<R: GameRules>: Send + Sync {
+    async fn create(&self, id: &Uuid, record: &GameRecord<R>) -> io::Result<()>;
+    async fn append_action(&self, id: &Uuid, action: &R::Action) -> io::Result<()>;
+    async fn remove(&self, id: &Uuid) -> io::Result<()>;
+    async fn load_all(&self) -> io::Result<Vec<(Uuid, GameRecord<R>)>>;
+    async fn record_result(&self, result: &R::Result) -> io::Result<()>;
+    async fn load_results(&self) -> io::Result<Vec<R::Result>>;
+    async fn register_player(&self, player: &str, token: &TokenHash) -> io::Result<()>;
+    async fn load_players(&self) -> io::Result<Vec<(String, TokenHash)>>;
+}
+
+pub fn to_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+pub fn from_millis(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
+pub fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Extension of the files games are written to before being renamed to their game file.
+const TEMP_EXTENSION: &str = "tmp";
+/// File the registered players are appended to, with the hash of their token.
+const PLAYERS_FILE: &str = "players";
+
+fn not_found(id: &Uuid) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("Game not found: {id}"))
+}
+
+pub struct MemoryStorage<R: GameRules> {
+    games: Mutex<HashMap<Uuid, GameRecord<R>>>,
+    results: Mutex<Vec<R::Result>>,
+    players: Mutex<Vec<(String, TokenHash)>>,
+}
+
+impl<R: GameRules> Default for MemoryStorage<R> {
+    fn default() -> Self {
+        Self {
+            games: Mutex::default(),
+            results: Mutex::default(),
+            players: Mutex::default(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<R: GameRules> GameStorage<R> for MemoryStorage<R> {
+    async fn create(&self, id: &Uuid, record: &GameRecord<R>) -> io::Result<()> {
+        self.games.lock().unwrap().insert(*id, record.clone());
+        Ok(())
+    }
+
+    async fn append_action(&self, id: &Uuid, action: &R::Action) -> io::Result<()> {
+        match self.games.lock().unwrap().get_mut(id) {
+            None => Err(not_found(id)),
+            Some(record) => {
+                record.actions.push(action.clone());
+                Ok(())
+            }
+        }
+    }
+
+    async fn remove(&self, id: &Uuid) -> io::Result<()> {
+        self.games.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    async fn load_all(&self) -> io::Result<Vec<(Uuid, GameRecord<R>)>> {
+        Ok(self
+            .games
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, record)| (*id, record.clone()))
+            .collect())
+    }
+
+    async fn record_result(&self, result: &R::Result) -> io::Result<()> {
+        self.results.lock().unwrap().push(result.clone());
+        Ok(())
+    }
+
+    async fn load_results(&self) -> io::Result<Vec<R::Result>> {
+        Ok(self.results.lock().unwrap().clone())
+    }
+
+    async fn register_player(&self, player: &str, token: &TokenHash) -> io::Result<()> {
+        self.players
+            .lock()
+            .unwrap()
+            .push((player.to_owned(), token.clone()));
+        Ok(())
+    }
+
+    async fn load_players(&self) -> io::Result<Vec<(String, TokenHash)>> {
+        Ok(self.players.lock().unwrap().clone())
+    }
+}
+
+/// How the games of one kind are written to files, [`FileStorage`] does the rest.
+pub trait FileFormat: GameRules {
+    /// Extension of the game files, telling the games of each kind apart.
+    const GAME_EXTENSION: &'static str;
+    /// File the results are appended to.
+    const RESULTS_FILE: &'static str;
+
+    /// The options of a setup written to the header line of its game, and the line following it.
+    fn format_setup(setup: &Self::Setup) -> (Vec<String>, String);
+    /// Options missing from the header keep their default value.
+    fn parse_setup(options: Vec<&str>, line: &str) -> io::Result<Self::Setup>;
+    fn parse_action(line: &str) -> io::Result<Self::Action>;
+    fn format_result(result: &Self::Result) -> String;
+    fn parse_result(line: &str) -> io::Result<Self::Result>;
+}
+
+/// Stores each game as a text file named after its id, holding the creation time followed by the
+/// hashes of the tokens and the options of the game, a line describing its deal and then one action per line, so
+/// moves are simply appended. Results of finished games are appended to a single results file,
+/// one per line, and registered players to a players file.
+pub struct FileStorage<R> {
+    dir: PathBuf,
+    rules: PhantomData<R>,
+}
+
+impl<R: FileFormat> FileStorage<R> {
+    pub async fn new(dir: PathBuf) -> io::Result<Self> {
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(Self {
+            dir,
+            rules: PhantomData,
+        })
+    }
+
+    fn path(&self, id: &Uuid) -> PathBuf {
+        self.dir
+            .join(id.to_string())
+            .with_extension(R::GAME_EXTENSION)
+    }
+
+    fn parse(content: &str) -> io::Result<GameRecord<R>> {
+        let mut lines = content.lines();
+        let mut header = lines.next().unwrap_or_default().split_whitespace();
+        let created_at = header
+            .next()
+            .and_then(|t| t.parse().ok())
+            .map(from_millis)
+            .ok_or_else(|| invalid_data("Invalid creation time".to_owned()))?;
+        let mut owner = None;
+        let mut spectator = None;
+        let mut player = None;
+        let mut options = Vec::new();
+        let hash = |v: &str| {
+            TokenHash::from_hex(v).ok_or_else(|| invalid_data(format!("Invalid token hash {v}")))
+        };
+        for token in header {
+            match token.split_once('=') {
+                Some(("owner-hash", v)) => owner = Some(hash(v)?),
+                Some(("spectator-hash", v)) => spectator = Some(hash(v)?),
+                Some(("player", v)) => player = Some(v.to_owned()),
+                _ => options.push(token),
+            }
+        }
+        let owner = owner.ok_or_else(|| invalid_data("Missing owner token".to_owned()))?;
+        let deal = lines
+            .next()
+            .ok_or_else(|| invalid_data("Missing deal".to_owned()))?;
+        let setup = R::parse_setup(options, deal)?;
+        let actions = lines.map(R::parse_action).collect::<io::Result<_>>()?;
+        Ok(GameRecord {
+            setup,
+            tokens: GameTokens { owner, spectator },
+            player,
+            created_at,
+            actions,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl<R: FileFormat> GameStorage<R> for FileStorage<R> {
+    async fn create(&self, id: &Uuid, record: &GameRecord<R>) -> io::Result<()> {
+        let created_at = to_millis(record.created_at);
+        let mut header = vec![format!("owner-hash={}", record.tokens.owner.as_hex())];
+        if let Some(spectator) = &record.tokens.spectator {
+            header.push(format!("spectator-hash={}", spectator.as_hex()));
+        }
+        if let Some(player) = &record.player {
+            header.push(format!("player={player}"));
+        }
+        let (options, deal) = R::format_setup(&record.setup);
+        header.extend(options);
+        let header = header.join(" ");
+        let mut content = format!("{created_at} {header}\n{deal}\n");
+        for action in record.actions.iter() {
+            content += &format!("{action}\n");
+        }
+        // Written aside then renamed over the game, so a crash never leaves half a record behind
+        let path = self.path(id);
+        let temp = path.with_extension(TEMP_EXTENSION);
+        let written = async {
+            let mut file = tokio::fs::File::create(&temp).await?;
+            file.write_all(content.as_bytes()).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&temp, &path).await
+        }
+        .await;
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&temp).await;
+        }
+        written
+    }
+
+    async fn append_action(&self, id: &Uuid, action: &R::Action) -> io::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(self.path(id))
+            .await?;
+        file.write_all(format!("{action}\n").as_bytes()).await?;
+        file.flush().await
+    }
+
+    async fn remove(&self, id: &Uuid) -> io::Result<()> {
+        match tokio::fs::remove_file(self.path(id)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    async fn load_all(&self) -> io::Result<Vec<(Uuid, GameRecord<R>)>> {
+        let mut games = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(e) if e == R::GAME_EXTENSION => (),
+                // Left by a crash while a game was written, the game itself was never stored
+                Some(TEMP_EXTENSION) => {
+                    tokio::fs::remove_file(&path).await?;
+                    continue;
+                }
+                _ => continue,
+            }
+            let id = match path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map(Uuid::from_str)
+            {
+                Some(Ok(id)) => id,
+                _ => continue,
+            };
+            match Self::parse(&tokio::fs::read_to_string(&path).await?) {
+                Ok(record) => games.push((id, record)),
+                Err(e) => tracing::warn!("Skipping corrupted game {id}: {e}"),
+            }
+        }
+        Ok(games)
+    }
+
+    async fn record_result(&self, result: &R::Result) -> io::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(R::RESULTS_FILE))
+            .await?;
+        file.write_all(format!("{}\n", R::format_result(result)).as_bytes())
+            .await?;
+        file.flush().await
+    }
+
+    async fn load_results(&self) -> io::Result<Vec<R::Result>> {
+        let content = match tokio::fs::read_to_string(self.dir.join(R::RESULTS_FILE)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            result => result?,
+        };
+        let mut results = Vec::new();
+        for line in content.lines() {
+            match R::parse_result(line) {
+                Ok(result) => results.push(result),
+                Err(e) => tracing::warn!("Skipping corrupted result: {e}"),
+            }
+        }
+        Ok(results)
+    }
+
+    async fn register_player(&self, player: &str, token: &TokenHash) -> io::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(PLAYERS_FILE))
+            .await?;
+        file.write_all(format!("{player} {}\n", token.as_hex()).as_bytes())
+            .await?;
+        file.sync_all().await
+    }
+
+    async fn load_players(&self) -> io::Result<Vec<(String, TokenHash)>> {
+        let content = match tokio::fs::read_to_string(self.dir.join(PLAYERS_FILE)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            result => result?,
+        };
+        let mut players = Vec::new();
+        for line in content.lines() {
+            let player = line
+                .split_once(' ')
+                .and_then(|(player, hash)| Some((player.to_owned(), TokenHash::from_hex(hash)?)));
+            match player {
+                Some(player) => players.push(player),
+                None => tracing::warn!("Skipping corrupted player: {line}"),
+            }
+        }
+        Ok(players)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::IssuedTokens;
+    use crate::solitaire::{Deal, GameResult, SolitaireRules};
+    use boards::random_engine::XorShifEngine;
+    use chrono::NaiveDate;
+    use solitaire_backend::{
+        shuffled_deck, Action, FoundationSource, GameOptions, ScoringMode, TableauSource, Variant,
+    };
+
+    #[tokio::test]
+    async fn file_storage_round_trip() {
+        let dir = std::env::temp_dir().join(format!("solitaire-storage-{}", Uuid::new_v4()));
+        let storage = FileStorage::<SolitaireRules>::new(dir.clone())
+            .await
+            .unwrap();
+        let id = Uuid::new_v4();
+        let issued = IssuedTokens::new(true);
+        let record = GameRecord {
+            setup: Deal {
+                deck: shuffled_deck(&mut XorShifEngine::new(42)),
+                options: GameOptions {
+                    variant: Variant::Thoughtful,
+                    draw_count: 3,
+                    redeal_limit: Some(2),
+                    scoring: ScoringMode::Vegas,
+                    seed: Some(42),
+                    auto_foundation: true,
+                },
+                daily: NaiveDate::from_ymd_opt(2022, 6, 23),
+            },
+            tokens: issued.hashes(),
+            player: Some("alice".to_owned()),
+            created_at: UNIX_EPOCH + Duration::from_millis(1_656_000_000_123),
+            actions: vec![Action::Draw],
+        };
+        let actions = [
+            Action::Draw,
+            Action::BuildFoundation {
+                src: FoundationSource::Tableau(3),
+            },
+            Action::BuildTableau {
+                src: TableauSource::Tableau { index: 1, size: 2 },
+                dst: 6,
+            },
+            Action::BuildTableau {
+                src: TableauSource::Upturned,
+                dst: 0,
+            },
+        ];
+
+        storage.create(&id, &record).await.unwrap();
+        // Only the game file is left, without the tokens themselves
+        let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+        let entry = entries.next_entry().await.unwrap().unwrap();
+        assert!(entries.next_entry().await.unwrap().is_none());
+        assert_eq!(entry.path(), storage.path(&id));
+        let content = tokio::fs::read_to_string(entry.path()).await.unwrap();
+        assert!(!content.contains(&issued.owner));
+        for action in actions.iter() {
+            storage.append_action(&id, action).await.unwrap();
+        }
+
+        let loaded = storage.load_all().await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        let (loaded_id, loaded) = &loaded[0];
+        assert_eq!(loaded_id, &id);
+        assert_eq!(loaded.created_at, record.created_at);
+        assert_eq!(loaded.setup.options, record.setup.options);
+        assert_eq!(loaded.tokens, record.tokens);
+        assert_eq!(loaded.player, record.player);
+        assert_eq!(loaded.setup.daily, record.setup.daily);
+        assert!(loaded.setup.deck.iter().eq(record.setup.deck.iter()));
+        assert_eq!(loaded.actions[0], Action::Draw);
+        assert_eq!(&loaded.actions[1..], &actions[..]);
+
+        let results = [
+            GameResult {
+                player: "alice".to_owned(),
+                variant: Variant::Thoughtful,
+                won: true,
+                moves: 120,
+                duration: Duration::from_millis(301_500),
+                score: Some(-12),
+                finished_at: UNIX_EPOCH + Duration::from_millis(1_656_000_400_000),
+                daily: NaiveDate::from_ymd_opt(2022, 6, 23),
+            },
+            GameResult {
+                player: "bob".to_owned(),
+                variant: Variant::Klondike,
+                won: false,
+                moves: 3,
+                duration: Duration::from_millis(2_000),
+                score: None,
+                finished_at: UNIX_EPOCH + Duration::from_millis(1_656_000_500_000),
+                daily: None,
+            },
+        ];
+        for result in results.iter() {
+            storage.record_result(result).await.unwrap();
+        }
+        assert_eq!(storage.load_results().await.unwrap(), results);
+
+        let alice = TokenHash::of("alice-secret");
+        storage.register_player("alice", &alice).await.unwrap();
+        assert_eq!(
+            storage.load_players().await.unwrap(),
+            [("alice".to_owned(), alice)]
+        );
+
+        storage.remove(&id).await.unwrap();
+        assert!(storage.load_all().await.unwrap().is_empty());
+        tokio::fs::remove_file(dir.join(SolitaireRules::RESULTS_FILE))
+            .await
+            .unwrap();
+        tokio::fs::remove_file(dir.join(PLAYERS_FILE))
+            .await
+            .unwrap();
+        tokio::fs::remove_dir(dir).await.unwrap();
+    }
+
+    #[test]
+    fn invalid_deals_are_not_loaded() {
+        let cards: Vec<_> = shuffled_deck(&mut XorShifEngine::new(1))
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let hash = TokenHash::of("secret");
+        let hash = hash.as_hex();
+        let parse = |options: &str, deck: &[String]| {
+            FileStorage::<SolitaireRules>::parse(&format!(
+                "1656000000123 owner-hash={hash} {options}\n{}\n",
+                deck.join(" ")
+            ))
+        };
+
+        assert!(parse("draw=3", &cards).is_ok());
+        assert!(parse("draw=2", &cards).is_err());
+        assert!(parse("seed=0", &cards).is_err());
+        assert!(parse("draw=3", &cards[1..]).is_err());
+        let mut twice = cards.clone();
+        twice[1] = twice[0].clone();
+        assert!(parse("draw=3", &twice).is_err());
+    }
+}
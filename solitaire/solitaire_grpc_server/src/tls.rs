@@ -0,0 +1,125 @@
+use crate::config::TlsConfig;
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// A client that does not finish its handshake in time is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+fn invalid_data(path: &Path, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {message}", path.display()),
+    )
+}
+
+fn read_pem(path: &Path) -> io::Result<Vec<Item>> {
+    rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_certificates(path: &Path) -> io::Result<Vec<rustls::Certificate>> {
+    let certificates: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(rustls::Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certificates.is_empty() {
+        Err(invalid_data(path, "No certificate found"))
+    } else {
+        Ok(certificates)
+    }
+}
+
+fn read_key(path: &Path) -> io::Result<rustls::PrivateKey> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(der) | Item::PKCS8Key(der) | Item::ECKey(der) => {
+                Some(rustls::PrivateKey(der))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| invalid_data(path, "No private key found"))
+}
+
+/// TLS settings of the gRPC server.
+pub fn grpc_config(tls: &TlsConfig) -> io::Result<tonic::transport::ServerTlsConfig> {
+    let identity =
+        tonic::transport::Identity::from_pem(std::fs::read(&tls.cert)?, std::fs::read(&tls.key)?);
+    let mut config = tonic::transport::ServerTlsConfig::new().identity(identity);
+    if let Some(client_ca) = &tls.client_ca {
+        config = config.client_ca_root(tonic::transport::Certificate::from_pem(std::fs::read(
+            client_ca,
+        )?));
+    }
+    Ok(config)
+}
+
+/// TLS settings of the HTTP gateway, the same as the gRPC server's.
+pub fn http_acceptor(tls: &TlsConfig) -> io::Result<TlsAcceptor> {
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls.client_ca {
+        None => builder.with_no_client_auth(),
+        Some(client_ca) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for certificate in read_certificates(client_ca)? {
+                roots
+                    .add(&certificate)
+                    .map_err(|e| invalid_data(client_ca, e))?;
+            }
+            builder
+                .with_client_cert_verifier(rustls::server::AllowAnyAuthenticatedClient::new(roots))
+        }
+    };
+    let mut config = builder
+        .with_single_cert(read_certificates(&tls.cert)?, read_key(&tls.key)?)
+        .map_err(|e| invalid_data(&tls.key, e))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accepts connections on `listener` and hands them over once their handshake is done, so a slow
+/// client does not hold back the others.
+pub fn incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> ReceiverStream<io::Result<TlsStream<TcpStream>>> {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        // Stops once the server is gone
+        while !tx.is_closed() {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    // Typically out of file descriptors, which may get better
+                    tracing::error!("Failed to accept connection: {e}");
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => tracing::debug!("TLS handshake with {peer} failed: {e}"),
+                    Err(_) => tracing::debug!("TLS handshake with {peer} timed out"),
+                }
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}